@@ -30,8 +30,8 @@ pub fn engine(depth: u8, moves: u8) {
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("all_possible_moves", |b| b.iter(|| test_all_possible_moves()));
-    c.bench_function("queen_capture", |b| b.iter(|| queen_capture()));
+    c.bench_function("all_possible_moves", |b| b.iter(test_all_possible_moves));
+    c.bench_function("queen_capture", |b| b.iter(queen_capture));
 }
 
 fn engine_benchmark(c: &mut Criterion) {
@@ -0,0 +1,205 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::evaluation::Tapered;
+use crate::params::ActivityParams;
+use crate::pieces::{
+    PieceColor,
+    PieceKind,
+};
+
+#[inline]
+fn bit(x: i32, y: i32) -> u64 {
+    1 << (y * 8 + x)
+}
+
+/// Rank counted from `color`'s own side, 0 for rank 1 up to 7 for rank 8
+#[inline]
+fn relative_rank(color: PieceColor, y: i32) -> i32 {
+    match color {
+        PieceColor::White => 7 - y,
+        PieceColor::Black => y,
+    }
+}
+
+/// Row offset of one step towards the enemy side
+#[inline]
+fn forward(color: PieceColor) -> i32 {
+    match color {
+        PieceColor::White => -1,
+        PieceColor::Black => 1,
+    }
+}
+
+/// The king's square and its neighbours, plus the three squares two steps in front of it
+fn king_zone(king: Coord, color: PieceColor) -> u64 {
+    let (x, y) = (king.x as i32, king.y as i32);
+    let mut zone = 0;
+    for dx in -1..=1 {
+        for dy in [-1, 0, 1, 2 * forward(color)] {
+            if (0..8).contains(&(x + dx)) && (0..8).contains(&(y + dy)) {
+                zone |= bit(x + dx, y + dy);
+            }
+        }
+    }
+    zone
+}
+
+/// Pawn bitmasks of both colors and the squares they attack
+struct Pawns {
+    placed: [u64; 2],
+    attacks: [u64; 2],
+}
+
+impl Pawns {
+    fn new(board: &Board) -> Self {
+        let mut pawns = Pawns { placed: [0; 2], attacks: [0; 2] };
+        for (y, row) in board.board.iter().enumerate() {
+            for (x, square) in row.iter().enumerate() {
+                if let Square::Occupied(piece) = square {
+                    if piece.kind == PieceKind::Pawn {
+                        pawns.placed[piece.color as usize] |= bit(x as i32, y as i32);
+                        pawns.attacks[piece.color as usize] |= board.attacks_from(Coord { x, y });
+                    }
+                }
+            }
+        }
+        pawns
+    }
+
+    fn on_file(&self, color: PieceColor, x: i32) -> bool {
+        (0..8).any(|y| self.placed[color as usize] & bit(x, y) != 0)
+    }
+
+    /// Whether an enemy pawn on a neighbouring file could ever advance to attack (x, y)
+    fn can_be_chased(&self, color: PieceColor, x: i32, y: i32) -> bool {
+        let enemy = self.placed[color.opposite() as usize];
+        [x - 1, x + 1].iter().filter(|&&f| (0..8).contains(&f)).any(|&f| {
+            (0..8).any(|r| (r - y) * forward(color) > 0 && enemy & bit(f, r) != 0)
+        })
+    }
+}
+
+/// Mobility, king safety and piece placement terms from White's point of view
+pub fn piece_activity(board: &Board, params: &ActivityParams) -> Tapered {
+    let pawns = Pawns::new(board);
+    let mut score = Tapered::default();
+    for color in [PieceColor::White, PieceColor::Black] {
+        let side = side_activity(board, params, &pawns, color);
+        match color {
+            PieceColor::White => score += side,
+            PieceColor::Black => score -= side,
+        }
+    }
+    score
+}
+
+fn side_activity(board: &Board, params: &ActivityParams, pawns: &Pawns, color: PieceColor) -> Tapered {
+    let enemy = color.opposite();
+    let (enemy_king, own_king) = match color {
+        PieceColor::White => (board.black_king, board.white_king),
+        PieceColor::Black => (board.white_king, board.black_king),
+    };
+    let enemy_zone = king_zone(enemy_king, enemy);
+
+    let mut own_pieces = 0;
+    for (y, row) in board.board.iter().enumerate() {
+        for (x, square) in row.iter().enumerate() {
+            if matches!(square, Square::Occupied(piece) if piece.color == color) {
+                own_pieces |= bit(x as i32, y as i32);
+            }
+        }
+    }
+    // Squares a piece can go to without landing on its own pieces or being chased off by a pawn
+    let mobility_area = !own_pieces & !pawns.attacks[enemy as usize];
+
+    let mut score = Tapered::default();
+    let mut bishops = 0;
+    let mut attackers = 0;
+    let mut attack_weight = 0;
+    for (y, row) in board.board.iter().enumerate() {
+        for (x, square) in row.iter().enumerate() {
+            let piece = match square {
+                Square::Occupied(piece) if piece.color == color => *piece,
+                _ => continue,
+            };
+            let index = match ActivityParams::piece_index(piece.kind) {
+                Some(index) => index,
+                None => continue,
+            };
+            let (x, y) = (x as i32, y as i32);
+            let attacks = board.attacks_from(Coord { x: x as usize, y: y as usize });
+
+            let mobility = (attacks & mobility_area).count_ones() as i32;
+            score += params.mobility[index] * (mobility - params.mobility_neutral[index]);
+
+            let zone_attacks = (attacks & enemy_zone).count_ones() as i32;
+            if zone_attacks > 0 {
+                attackers += 1;
+                attack_weight += params.king_attack_weight[index] * zone_attacks;
+            }
+
+            match piece.kind {
+                PieceKind::Bishop => bishops += 1,
+                PieceKind::Rook => {
+                    if !pawns.on_file(color, x) {
+                        score += if pawns.on_file(enemy, x) { params.rook_semi_open_file } else { params.rook_open_file };
+                    }
+                    // The 7th rank only matters while it traps the enemy king or holds enemy pawns
+                    let seventh = (0..8).fold(0, |mask, f| mask | bit(f, y));
+                    if relative_rank(color, y) == 6
+                        && (relative_rank(enemy, enemy_king.y as i32) == 0 || pawns.placed[enemy as usize] & seventh != 0)
+                    {
+                        score += params.rook_seventh_rank;
+                    }
+                }
+                PieceKind::Knight => {
+                    let rank = relative_rank(color, y);
+                    let supported = pawns.attacks[color as usize] & bit(x, y) != 0;
+                    if (3..=5).contains(&rank) && supported && !pawns.can_be_chased(color, x, y) {
+                        score += params.knight_outpost;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if bishops >= 2 {
+        score += params.bishop_pair;
+    }
+    // A single attacker is rarely dangerous, so the safety table only applies from two upwards
+    if attackers >= 2 {
+        let safety = &params.king_safety;
+        score += Tapered::new(safety[(attack_weight as usize).min(safety.len() - 1)], 0);
+    }
+    score + king_shelter(params, pawns, own_king, color)
+}
+
+/// Pawn shield and open files around a king that is still on its first two ranks
+fn king_shelter(params: &ActivityParams, pawns: &Pawns, king: Coord, color: PieceColor) -> Tapered {
+    let (x, y) = (king.x as i32, king.y as i32);
+    if relative_rank(color, y) > 1 {
+        return Tapered::default();
+    }
+    let own = pawns.placed[color as usize];
+    let step = forward(color);
+    let mut score = Tapered::default();
+    for f in (x - 1..=x + 1).filter(|f| (0..8).contains(f)) {
+        for (distance, bonus) in params.pawn_shield.iter().enumerate() {
+            let r = y + step * (distance as i32 + 1);
+            if (0..8).contains(&r) && own & bit(f, r) != 0 {
+                score += *bonus;
+            }
+        }
+        if !pawns.on_file(color, f) {
+            score += params.king_semi_open_file;
+            if !pawns.on_file(color.opposite(), f) {
+                score += params.king_open_file;
+            }
+        }
+    }
+    score
+}
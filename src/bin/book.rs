@@ -0,0 +1,66 @@
+use chess_engine::book::{BookBuilder, BookBuilderOptions, BookWeighting};
+use chess_engine::pgn::read_games;
+use std::{fs::File, io::BufReader};
+
+const USAGE: &str = "<output.bin> <games.pgn>... [--max-ply N] [--min-games N] [--min-rating N] [--weighting games|results]";
+
+/// Usage: book <output.bin> <games.pgn>... [--max-ply N] [--min-games N] [--min-rating N] [--weighting games|results]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let usage = || -> ! {
+        eprintln!("Usage: {} {USAGE}", args[0]);
+        std::process::exit(2);
+    };
+
+    let mut options = BookBuilderOptions::default();
+    let mut files = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            files.push(arg);
+            continue;
+        }
+        let value = rest.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--max-ply" => options.max_ply = value.parse()?,
+            "--min-games" => options.min_games = value.parse()?,
+            "--min-rating" => options.min_rating = Some(value.parse()?),
+            "--weighting" => {
+                options.weighting = match value.as_str() {
+                    "games" => BookWeighting::Games,
+                    "results" => BookWeighting::Results,
+                    _ => usage(),
+                }
+            }
+            _ => usage(),
+        }
+    }
+    if files.len() < 2 {
+        usage();
+    }
+
+    let mut builder = BookBuilder::new(options);
+    let (mut skipped, mut invalid) = (0, 0);
+    for path in &files[1..] {
+        for game in read_games(BufReader::new(File::open(path)?)) {
+            match builder.add_game(&game?) {
+                Ok(true) => {}
+                Ok(false) => skipped += 1,
+                Err(desc) => {
+                    eprintln!("{path}: skipping game: {desc}");
+                    invalid += 1;
+                }
+            }
+        }
+    }
+
+    let book = builder.build();
+    book.save(files[0])?;
+    println!(
+        "{} games added ({skipped} filtered out, {invalid} invalid), {} entries written to {}",
+        builder.games(),
+        book.len(),
+        files[0]
+    );
+    Ok(())
+}
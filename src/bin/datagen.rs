@@ -0,0 +1,26 @@
+use chess_engine::datagen::{SelfPlay, SelfPlayOptions};
+use std::{fs::File, io::BufWriter};
+
+/// Usage: datagen <output> <games> [nodes] [seed]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 || args.len() > 5 {
+        eprintln!("Usage: {} <output> <games> [nodes] [seed]", args[0]);
+        std::process::exit(2);
+    }
+    let defaults = SelfPlayOptions::default();
+    let options = SelfPlayOptions {
+        games: args[2].parse()?,
+        nodes: args.get(3).map_or(Ok(defaults.nodes), |n| n.parse())?,
+        seed: args.get(4).map_or(Ok(defaults.seed), |n| n.parse())?,
+        ..defaults
+    };
+
+    let mut out = BufWriter::new(File::create(&args[1])?);
+    let stats = SelfPlay::new(options).generate(&mut out)?;
+    println!(
+        "{} games (+{} ={} -{}), {} positions written to {}",
+        stats.games, stats.white_wins, stats.draws, stats.black_wins, stats.positions, args[1]
+    );
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use chess_engine::{
+    params::EvalParams,
+    tuner::{load_positions, Tuner, TunerOptions},
+};
+
+/// Usage: tune <positions> <output.toml> [start.toml]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("Usage: {} <positions> <output.toml> [start.toml]", args[0]);
+        std::process::exit(2);
+    }
+    let params = match args.get(3) {
+        Some(path) => EvalParams::load(path)?,
+        None => EvalParams::default(),
+    };
+
+    let positions = load_positions(&args[1])?;
+    println!("Loaded {} positions", positions.len());
+    let mut tuner = Tuner::new(positions, TunerOptions::default());
+    let error = tuner.fit_scaling(&params);
+    println!("Scaling {:.3}, error {:.6}", tuner.scaling, error);
+
+    let output = &args[2];
+    let tuned = tuner.tune(params, |iteration, error| println!("Iteration {iteration}, error {error:.6}"));
+    tuned.save(output)?;
+    println!("Saved to {output}");
+    Ok(())
+}
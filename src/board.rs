@@ -6,6 +6,7 @@ use crate::pieces::{
 
 use std::{fmt, cmp::Ordering};
 use colored::*;
+use lazy_static::lazy_static;
 
 #[derive(Debug)]
 pub enum BoardError {
@@ -81,6 +82,54 @@ impl Move {
     }
 }
 
+struct ZobristKeys {
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+}
+
+impl ZobristKeys {
+    fn new() -> Self {
+        // Fixed seed so keys (and therefore hashes) are stable between runs
+        let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = || {
+            // splitmix64
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+
+        let mut keys = ZobristKeys {
+            pieces: [[[0; 64]; 6]; 2],
+            black_to_move: 0,
+            castling: [0; 4],
+            en_passant: [0; 8],
+        };
+        for color in keys.pieces.iter_mut() {
+            for kind in color.iter_mut() {
+                for square in kind.iter_mut() {
+                    *square = next();
+                }
+            }
+        }
+        keys.black_to_move = next();
+        for key in keys.castling.iter_mut() {
+            *key = next();
+        }
+        for key in keys.en_passant.iter_mut() {
+            *key = next();
+        }
+        keys
+    }
+}
+
+lazy_static! {
+    static ref ZOBRIST: ZobristKeys = ZobristKeys::new();
+}
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Board {
     pub board: [[Square; 8]; 8],
@@ -97,6 +146,23 @@ pub struct Board {
     pub fullmove_number: u8,
 }
 
+impl Default for Board {
+    fn default() -> Board {
+        let mut board = Board::empty();
+        board.board = [
+            [Square::Occupied(Piece::rook(PieceColor::Black)), Square::Occupied(Piece::knight(PieceColor::Black)), Square::Occupied(Piece::bishop(PieceColor::Black)), Square::Occupied(Piece::queen(PieceColor::Black)), Square::Occupied(Piece::king(PieceColor::Black)), Square::Occupied(Piece::bishop(PieceColor::Black)), Square::Occupied(Piece::knight(PieceColor::Black)), Square::Occupied(Piece::rook(PieceColor::Black))],
+            [Square::Occupied(Piece::pawn(PieceColor::Black)); 8],
+            [Square::Empty; 8],
+            [Square::Empty; 8],
+            [Square::Empty; 8],
+            [Square::Empty; 8],
+            [Square::Occupied(Piece::pawn(PieceColor::White)); 8],
+            [Square::Occupied(Piece::rook(PieceColor::White)), Square::Occupied(Piece::knight(PieceColor::White)), Square::Occupied(Piece::bishop(PieceColor::White)), Square::Occupied(Piece::queen(PieceColor::White)), Square::Occupied(Piece::king(PieceColor::White)), Square::Occupied(Piece::bishop(PieceColor::White)), Square::Occupied(Piece::knight(PieceColor::White)), Square::Occupied(Piece::rook(PieceColor::White))],
+        ];
+        board
+    }
+}
+
 impl Board {
     pub fn empty() -> Board {
         Board {
@@ -115,21 +181,6 @@ impl Board {
         }
     }
 
-    pub fn default() -> Board {
-        let mut board = Board::empty();
-        board.board = [
-            [Square::Occupied(Piece::rook(PieceColor::Black)), Square::Occupied(Piece::knight(PieceColor::Black)), Square::Occupied(Piece::bishop(PieceColor::Black)), Square::Occupied(Piece::queen(PieceColor::Black)), Square::Occupied(Piece::king(PieceColor::Black)), Square::Occupied(Piece::bishop(PieceColor::Black)), Square::Occupied(Piece::knight(PieceColor::Black)), Square::Occupied(Piece::rook(PieceColor::Black))],
-            [Square::Occupied(Piece::pawn(PieceColor::Black)); 8],
-            [Square::Empty; 8],
-            [Square::Empty; 8],
-            [Square::Empty; 8],
-            [Square::Empty; 8],
-            [Square::Occupied(Piece::pawn(PieceColor::White)); 8],
-            [Square::Occupied(Piece::rook(PieceColor::White)), Square::Occupied(Piece::knight(PieceColor::White)), Square::Occupied(Piece::bishop(PieceColor::White)), Square::Occupied(Piece::queen(PieceColor::White)), Square::Occupied(Piece::king(PieceColor::White)), Square::Occupied(Piece::bishop(PieceColor::White)), Square::Occupied(Piece::knight(PieceColor::White)), Square::Occupied(Piece::rook(PieceColor::White))],
-        ];
-        board
-    }
-
     pub fn from_fen(fen: &str) -> Result<Board> {
         let mut board = Board::empty();
         let mut x: usize = 0;
@@ -182,26 +233,10 @@ impl Board {
         }
 
         let castle = fen.next().ok_or(BoardError::ParseError("Invalid length of FEN".to_string()))?;
-        if castle.contains('K') {
-            board.white_castle.0 = true;
-        } else {
-            board.white_castle.0 = false;
-        }
-        if castle.contains('Q') {
-            board.white_castle.1 = true;
-        } else {
-            board.white_castle.1 = false;
-        }
-        if castle.contains('k') {
-            board.black_castle.0 = true;
-        } else {
-            board.black_castle.0 = false;
-        }
-        if castle.contains('q') {
-            board.black_castle.1 = true;
-        } else {
-            board.black_castle.1 = false;
-        }
+        board.white_castle.0 = castle.contains('K');
+        board.white_castle.1 = castle.contains('Q');
+        board.black_castle.0 = castle.contains('k');
+        board.black_castle.1 = castle.contains('q');
 
         let en_passant = fen.next().ok_or(BoardError::ParseError("Invalid length of FEN".to_string()))?;
         if en_passant != "-" {
@@ -219,6 +254,83 @@ impl Board {
         Ok(board)
     }
 
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for (y, row) in self.board.iter().enumerate() {
+            let mut empty = 0;
+            for square in row.iter() {
+                match square {
+                    Square::Empty => empty += 1,
+                    Square::Occupied(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece.fen_char());
+                    }
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.turn == PieceColor::White { " w " } else { " b " });
+        let castling: String = [(self.white_castle.0, 'K'), (self.white_castle.1, 'Q'), (self.black_castle.0, 'k'), (self.black_castle.1, 'q')]
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .map(|(_, c)| *c)
+            .collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+        fen.push(' ');
+        fen.push_str(&self.en_passant.map_or("-".to_string(), |coord| coord.to_notation()));
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    /// Zobrist hash of the position: piece placement, side to move, castling rights and en passant square
+    pub fn zobrist_key(&self) -> u64 {
+        let mut key = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Square::Occupied(piece) = self.board[y][x] {
+                    key ^= ZOBRIST.pieces[piece.color as usize][piece.kind as usize][y * 8 + x];
+                }
+            }
+        }
+        if self.turn == PieceColor::Black {
+            key ^= ZOBRIST.black_to_move;
+        }
+        let castling = [self.white_castle.0, self.white_castle.1, self.black_castle.0, self.black_castle.1];
+        for (i, allowed) in castling.iter().enumerate() {
+            if *allowed {
+                key ^= ZOBRIST.castling[i];
+            }
+        }
+        if let Some(en_passant) = self.en_passant {
+            key ^= ZOBRIST.en_passant[en_passant.x];
+        }
+        key
+    }
+
+    /// Zobrist hash of the pawn placement only
+    pub fn pawn_key(&self) -> u64 {
+        let mut key = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Square::Occupied(piece) = self.board[y][x] {
+                    if piece.kind == PieceKind::Pawn {
+                        key ^= ZOBRIST.pieces[piece.color as usize][PieceKind::Pawn as usize][y * 8 + x];
+                    }
+                }
+            }
+        }
+        key
+    }
+
     pub fn pretty_print_board(&self) {
         for i in 0..8 {
             for j in 0..8 {
@@ -306,6 +418,12 @@ impl Board {
         if !self.is_valid_move(mv) {
             return Err(BoardError::MoveError(format!("Invalid move from {} to {}", from.to_notation(), to.to_notation())));
         }
+        // Pawn moves and captures (en passant included) are irreversible and restart the fifty-move count
+        let irreversible = self.piece_at(to).is_some() || matches!(self.piece_at(from), Some(Piece { kind: PieceKind::Pawn, .. }));
+        self.halfmove_clock = if irreversible { 0 } else { self.halfmove_clock.saturating_add(1) };
+        if self.turn == PieceColor::Black {
+            self.fullmove_number = self.fullmove_number.saturating_add(1);
+        }
         if self.is_castle(from, to) {
             self.do_castle(from, to)?;
         } else if self.is_en_passant(from, to) {
@@ -324,6 +442,13 @@ impl Board {
         self.check_end_conditions();
     }
 
+    /// Passes the turn without moving a piece. Only meant for null-move pruning in the search,
+    /// the side to move must not be in check.
+    pub fn make_null_move(&mut self) {
+        self.en_passant = None;
+        self.end_turn();
+    }
+
     pub fn parse_move(&self, notation: &str) -> Result<Move> {
         let mut chars = notation.chars();
         let from = chars.by_ref().take(2).collect::<String>();
@@ -545,6 +670,72 @@ impl Board {
         false
     }
 
+    /// Squares attacked by the piece on `from` as a bitmask of `y * 8 + x` bits. Squares holding pieces of
+    /// the same color are included (they are defended), squares behind the first blocker are not.
+    pub fn attacks_from(&self, from: Coord) -> u64 {
+        const KNIGHT: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+        const KING: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+        const DIAGONAL: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+        const STRAIGHT: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+        let piece = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+        let (x, y) = (from.x as i32, from.y as i32);
+        let on_board = |x: i32, y: i32| (0..8).contains(&x) && (0..8).contains(&y);
+        let mut attacks = 0;
+        let mut steps = |offsets: &[(i32, i32)]| {
+            for (dx, dy) in offsets {
+                if on_board(x + dx, y + dy) {
+                    attacks |= 1 << ((y + dy) * 8 + x + dx);
+                }
+            }
+        };
+        match piece.kind {
+            PieceKind::Pawn => {
+                let dy = if piece.color == PieceColor::White { -1 } else { 1 };
+                steps(&[(-1, dy), (1, dy)]);
+            }
+            PieceKind::Knight => steps(&KNIGHT),
+            PieceKind::King => steps(&KING),
+            _ => {
+                let directions: &[(i32, i32)] = match piece.kind {
+                    PieceKind::Bishop => &DIAGONAL,
+                    PieceKind::Rook => &STRAIGHT,
+                    _ => &KING,
+                };
+                for (dx, dy) in directions {
+                    let (mut to_x, mut to_y) = (x + dx, y + dy);
+                    while on_board(to_x, to_y) {
+                        attacks |= 1 << (to_y * 8 + to_x);
+                        if self.board[to_y as usize][to_x as usize] != Square::Empty {
+                            break;
+                        }
+                        to_x += dx;
+                        to_y += dy;
+                    }
+                }
+            }
+        }
+        attacks
+    }
+
+    /// Union of [`Board::attacks_from`] over every piece of `color`
+    pub fn attacked_by(&self, color: PieceColor) -> u64 {
+        let mut attacks = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Square::Occupied(piece) = self.board[y][x] {
+                    if piece.color == color {
+                        attacks |= self.attacks_from(Coord { x, y });
+                    }
+                }
+            }
+        }
+        attacks
+    }
+
     pub fn list_all_valid_moves(&self) -> Vec<Move> {
         let mut moves = Vec::new();
         for y in 0..8 {
@@ -606,7 +797,9 @@ impl Board {
             for x in 0..8 {
                 let coord = Coord { x, y };
                 if let Some(piece) = self.piece_at(coord) {
-                    if piece.color != color && self.can_attack_square(coord, king_coord) {
+                    // A king two files away only has the shape of a castle, which never captures
+                    let castle_shape = piece.kind == PieceKind::King && coord.x.abs_diff(king_coord.x) > 1;
+                    if piece.color != color && self.can_attack_square(coord, king_coord) && !castle_shape {
                         return true;
                     }
                 }
@@ -0,0 +1,358 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::engine::{
+    is_mate_score,
+    SearchLimits,
+    SearchOptions,
+    SearchPool,
+    StopSignal,
+};
+use crate::pieces::{
+    Piece,
+    PieceColor,
+    PieceKind,
+};
+use crate::tuner::TuningPosition;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::io::{self, Read, Write};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+use std::thread;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    WhiteWin,
+    Draw,
+    BlackWin,
+}
+
+impl GameResult {
+    /// 1.0 for a White win, 0.5 for a draw and 0.0 for a Black win
+    pub fn score(self) -> f64 {
+        match self {
+            GameResult::WhiteWin => 1.0,
+            GameResult::Draw => 0.5,
+            GameResult::BlackWin => 0.0,
+        }
+    }
+
+    fn win_for(color: PieceColor) -> Self {
+        match color {
+            PieceColor::White => GameResult::WhiteWin,
+            PieceColor::Black => GameResult::BlackWin,
+        }
+    }
+}
+
+/// A position in 28 bytes: the occupied squares as a bitboard (`y * 8 + x` bits, 8 bytes), one nibble per
+/// occupied square in the same order holding `kind | color << 3` (16 bytes), then the side to move and
+/// castling rights (1 byte), the en passant file or 0xFF (1 byte), the halfmove clock and fullmove
+/// number (1 byte each).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PackedPosition([u8; 28]);
+
+impl PackedPosition {
+    pub fn pack(board: &Board) -> Self {
+        let mut bytes = [0u8; 28];
+        let mut occupancy = 0u64;
+        let mut nibbles = 0usize;
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Square::Occupied(piece) = board.board[y][x] {
+                    occupancy |= 1 << (y * 8 + x);
+                    let code = piece.kind as u8 | (piece.color as u8) << 3;
+                    bytes[8 + nibbles / 2] |= code << (4 * (nibbles % 2));
+                    nibbles += 1;
+                }
+            }
+        }
+        bytes[..8].copy_from_slice(&occupancy.to_le_bytes());
+        let flags = [board.turn == PieceColor::Black, board.white_castle.0, board.white_castle.1, board.black_castle.0, board.black_castle.1];
+        bytes[24] = flags.iter().enumerate().map(|(i, &set)| (set as u8) << i).sum();
+        bytes[25] = board.en_passant.map_or(0xFF, |coord| coord.x as u8);
+        bytes[26] = board.halfmove_clock;
+        bytes[27] = board.fullmove_number;
+        PackedPosition(bytes)
+    }
+
+    pub fn unpack(&self) -> Result<Board, String> {
+        let bytes = &self.0;
+        let occupancy = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        if occupancy.count_ones() > 32 {
+            return Err("More than 32 pieces".to_string());
+        }
+        let kinds = [PieceKind::Pawn, PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen, PieceKind::King];
+        let mut board = Board::empty();
+        let mut nibbles = 0usize;
+        for square in 0..64 {
+            if occupancy & (1 << square) == 0 {
+                continue;
+            }
+            let code = (bytes[8 + nibbles / 2] >> (4 * (nibbles % 2))) & 0xF;
+            nibbles += 1;
+            let kind = *kinds.get((code & 7) as usize).ok_or("Invalid piece code")?;
+            let color = if code & 8 == 0 { PieceColor::White } else { PieceColor::Black };
+            board.board[square / 8][square % 8] = Square::Occupied(Piece { color, kind });
+        }
+
+        // Going through FEN sets up the kings, check and end-of-game state the same way as anywhere else
+        let mut fen = board.to_fen();
+        fen.truncate(fen.find(' ').unwrap());
+        let flag = |i: u8| bytes[24] & (1 << i) != 0;
+        fen.push_str(if flag(0) { " b " } else { " w " });
+        let castling: String = [(1, 'K'), (2, 'Q'), (3, 'k'), (4, 'q')].iter().filter(|(i, _)| flag(*i)).map(|(_, c)| *c).collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+        match bytes[25] {
+            0xFF => fen.push_str(" -"),
+            file @ 0..=7 => {
+                let y = if flag(0) { 5 } else { 2 };
+                fen.push(' ');
+                fen.push_str(&Coord { x: file as usize, y }.to_notation());
+            }
+            _ => return Err("Invalid en passant file".to_string()),
+        }
+        fen.push_str(&format!(" {} {}", bytes[26], bytes[27]));
+        Board::from_fen(&fen).map_err(|err| err.to_string())
+    }
+}
+
+/// One training sample as written by the self-play generator
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TrainingRecord {
+    pub board: Board,
+    /// Search score in centipawns from White's point of view
+    pub score: i16,
+    pub result: GameResult,
+}
+
+impl TrainingRecord {
+    /// Size of a record on disk: a [`PackedPosition`], the score (i16, little endian), the result
+    /// (0 Black win, 1 draw, 2 White win) and a padding byte
+    pub const SIZE: usize = 32;
+
+    pub fn to_bytes(&self) -> [u8; TrainingRecord::SIZE] {
+        let mut bytes = [0u8; TrainingRecord::SIZE];
+        bytes[..28].copy_from_slice(&PackedPosition::pack(&self.board).0);
+        bytes[28..30].copy_from_slice(&self.score.to_le_bytes());
+        bytes[30] = match self.result {
+            GameResult::BlackWin => 0,
+            GameResult::Draw => 1,
+            GameResult::WhiteWin => 2,
+        };
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; TrainingRecord::SIZE]) -> Result<Self, String> {
+        let board = PackedPosition(bytes[..28].try_into().unwrap()).unpack()?;
+        let score = i16::from_le_bytes([bytes[28], bytes[29]]);
+        let result = match bytes[30] {
+            0 => GameResult::BlackWin,
+            1 => GameResult::Draw,
+            2 => GameResult::WhiteWin,
+            other => return Err(format!("Invalid game result {other}")),
+        };
+        Ok(TrainingRecord { board, score, result })
+    }
+}
+
+impl From<TrainingRecord> for TuningPosition {
+    fn from(record: TrainingRecord) -> Self {
+        TuningPosition { board: record.board, result: record.result.score() }
+    }
+}
+
+/// Reads every record from a file written by [`SelfPlay::generate`]
+pub fn read_records(mut reader: impl Read) -> io::Result<Vec<TrainingRecord>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() % TrainingRecord::SIZE != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "File is not a whole number of records"));
+    }
+    bytes
+        .chunks_exact(TrainingRecord::SIZE)
+        .map(|chunk| TrainingRecord::from_bytes(chunk.try_into().unwrap()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SelfPlayOptions {
+    pub games: usize,
+    /// 0 uses every available core, each thread plays its own games
+    pub threads: usize,
+    /// Search budget per move
+    pub nodes: u64,
+    /// Random moves played from the starting position before the engines take over
+    pub random_plies: usize,
+    /// Games still going after this many plies are adjudicated as draws
+    pub max_plies: usize,
+    /// Game `i` is played from seed `seed + i`, so runs can be repeated
+    pub seed: u64,
+    /// Leave out positions where the side to move is in check
+    pub skip_in_check: bool,
+    /// Leave out positions where the best move is a capture or promotion, since their static
+    /// evaluation is about to change
+    pub skip_tactical: bool,
+    pub hash_mb: usize,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> Self {
+        SelfPlayOptions {
+            games: 100,
+            threads: 0,
+            nodes: 5_000,
+            random_plies: 8,
+            max_plies: 400,
+            seed: 0,
+            skip_in_check: true,
+            skip_tactical: true,
+            hash_mb: 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SelfPlayStats {
+    pub games: usize,
+    pub positions: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+    pub black_wins: usize,
+}
+
+/// Engine-vs-engine games at a fixed node count from randomized openings, recording every position
+/// that passes the filters with its search score and the final game result
+pub struct SelfPlay {
+    options: SelfPlayOptions,
+}
+
+impl SelfPlay {
+    pub fn new(options: SelfPlayOptions) -> Self {
+        SelfPlay { options }
+    }
+
+    /// Plays all the games and writes their records to `out` as each game finishes
+    pub fn generate(&self, out: &mut (impl Write + Send)) -> io::Result<SelfPlayStats> {
+        let threads = match self.options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let next_game = AtomicUsize::new(0);
+        let shared = Mutex::new((out, SelfPlayStats::default()));
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.min(self.options.games.max(1)))
+                .map(|_| {
+                    scope.spawn(|| -> io::Result<()> {
+                        let pool = SearchPool::new(1, self.options.hash_mb);
+                        loop {
+                            let game = next_game.fetch_add(1, Ordering::Relaxed);
+                            if game >= self.options.games {
+                                return Ok(());
+                            }
+                            let (records, result) = self.play_game(&pool, self.options.seed.wrapping_add(game as u64));
+                            let mut bytes = Vec::with_capacity(records.len() * TrainingRecord::SIZE);
+                            for record in &records {
+                                bytes.extend_from_slice(&record.to_bytes());
+                            }
+
+                            let mut shared = shared.lock().unwrap();
+                            let (out, stats) = &mut *shared;
+                            out.write_all(&bytes)?;
+                            stats.games += 1;
+                            stats.positions += records.len();
+                            match result {
+                                GameResult::WhiteWin => stats.white_wins += 1,
+                                GameResult::Draw => stats.draws += 1,
+                                GameResult::BlackWin => stats.black_wins += 1,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            workers.into_iter().try_for_each(|worker| worker.join().unwrap())
+        })?;
+
+        let (out, stats) = shared.into_inner().unwrap();
+        out.flush()?;
+        Ok(stats)
+    }
+
+    /// Plays one game from `seed`, returning the records that passed the filters and the result
+    pub fn play_game(&self, pool: &SearchPool, seed: u64) -> (Vec<TrainingRecord>, GameResult) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        pool.clear();
+        let mut board = self.random_opening(&mut rng);
+        let limits = SearchLimits { nodes: Some(self.options.nodes), ..Default::default() };
+        let stop = StopSignal::new();
+
+        let mut history = vec![board.zobrist_key()];
+        let mut samples = Vec::new();
+        let result = loop {
+            if let Some(result) = self.game_over(&board, &history) {
+                break result;
+            }
+            let found = match pool.search(&board, &history[..history.len() - 1], &limits, SearchOptions::default(), &stop) {
+                Some(found) => found,
+                None => break GameResult::Draw,
+            };
+
+            let tactical = board.piece_at(found.best_move.to).is_some()
+                || board.piece_at(found.best_move.from).is_some_and(|piece| piece.kind == PieceKind::Pawn)
+                    && (found.best_move.to.x != found.best_move.from.x || found.best_move.to.y % 7 == 0);
+            let skip = (self.options.skip_in_check && board.get_check())
+                || (self.options.skip_tactical && tactical)
+                || is_mate_score(found.score);
+            if !skip {
+                let score = match board.turn {
+                    PieceColor::White => found.score,
+                    PieceColor::Black => -found.score,
+                };
+                samples.push((board, score.clamp(i16::MIN as i32, i16::MAX as i32) as i16));
+            }
+
+            board.do_move_from_coord(found.best_move).expect("search returned an illegal move");
+            history.push(board.zobrist_key());
+        };
+
+        let records = samples.into_iter().map(|(board, score)| TrainingRecord { board, score, result }).collect();
+        (records, result)
+    }
+
+    /// Plays random legal moves from the starting position, starting over if they end the game
+    fn random_opening(&self, rng: &mut StdRng) -> Board {
+        'retry: loop {
+            let mut board = Board::default();
+            for _ in 0..self.options.random_plies {
+                let moves = board.list_all_valid_moves();
+                let mv = *moves.choose(rng).expect("the game ended moves ago");
+                board.do_move_from_coord(mv).unwrap();
+                if !board.has_valid_moves() {
+                    continue 'retry;
+                }
+            }
+            return board;
+        }
+    }
+
+    fn game_over(&self, board: &Board, history: &[u64]) -> Option<GameResult> {
+        if board.get_checkmate() {
+            return Some(GameResult::win_for(board.turn.opposite()));
+        }
+        let key = history[history.len() - 1];
+        let repetitions = history.iter().filter(|&&k| k == key).count();
+        let only_kings = board.board.iter().flatten().all(|square| match square {
+            Square::Occupied(piece) => piece.kind == PieceKind::King,
+            Square::Empty => true,
+        });
+        if board.get_stalemate() || board.halfmove_clock >= 100 || repetitions >= 3 || only_kings || history.len() > self.options.max_plies {
+            return Some(GameResult::Draw);
+        }
+        None
+    }
+}
@@ -0,0 +1,188 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::pieces::{
+    PieceColor,
+    PieceKind,
+};
+
+/// Scale factors are fractions of this
+pub const SCALE_NORMAL: i32 = 64;
+/// Added for the strong side once the defender is down to a bare king and mate can be forced, so the
+/// search heads for these endings and the score keeps rising as the mate gets closer
+const KNOWN_WIN: i32 = 500;
+/// Per step of Manhattan distance of the losing king from the four center squares
+const PUSH_TO_EDGE: i32 = 20;
+/// Per step the kings are closer than the width of the board
+const PUSH_CLOSE: i32 = 20;
+/// Per step of Manhattan distance the losing king is closer to a corner the bishop covers, in KBNK
+const PUSH_TO_CORNER: i32 = 25;
+
+/// Scales for endings that are hard to win
+const SCALE_OPPOSITE_BISHOPS: i32 = 24;
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES: i32 = 48;
+/// Up at most a minor piece without pawns: SCALE_MINOR_UP against at most a minor, SCALE_MINOR_UP_MAJORS otherwise
+const SCALE_MINOR_UP: i32 = 4;
+const SCALE_MINOR_UP_MAJORS: i32 = 14;
+
+/// Pieces of one side as far as recognizing endgames goes
+#[derive(Copy, Clone)]
+struct Side {
+    counts: [i32; 6],
+    /// Bishops on squares with x + y even, which are the light ones (a8 is light)
+    light_bishops: i32,
+    /// Files holding pawns, one bit per file
+    pawn_files: u8,
+    /// Rows holding pawns, one bit per y
+    pawn_ranks: u8,
+    king: Coord,
+}
+
+impl Side {
+    fn count(&self, kind: PieceKind) -> i32 {
+        self.counts[kind as usize]
+    }
+
+    fn dark_bishops(&self) -> i32 {
+        self.count(PieceKind::Bishop) - self.light_bishops
+    }
+
+    /// Value of the pieces besides pawns and king in pawn units, minors counting 3
+    fn non_pawn_material(&self) -> i32 {
+        3 * (self.count(PieceKind::Knight) + self.count(PieceKind::Bishop)) + 5 * self.count(PieceKind::Rook) + 9 * self.count(PieceKind::Queen)
+    }
+
+    fn is_bare_king(&self) -> bool {
+        self.non_pawn_material() == 0 && self.count(PieceKind::Pawn) == 0
+    }
+
+    /// Pieces that mate a bare king by force, pawns aside
+    fn can_force_mate(&self) -> bool {
+        self.count(PieceKind::Queen) > 0
+            || self.count(PieceKind::Rook) > 0
+            || (self.light_bishops > 0 && self.dark_bishops() > 0)
+            || (self.count(PieceKind::Bishop) > 0 && self.count(PieceKind::Knight) > 0)
+    }
+
+    /// Only a bishop and a knight, the hardest of the forced mates
+    fn is_bishop_and_knight(&self) -> bool {
+        self.count(PieceKind::Bishop) == 1 && self.count(PieceKind::Knight) == 1 && self.non_pawn_material() == 6 && self.count(PieceKind::Pawn) == 0
+    }
+}
+
+fn sides(board: &Board) -> [Side; 2] {
+    let empty = Side { counts: [0; 6], light_bishops: 0, pawn_files: 0, pawn_ranks: 0, king: Coord { x: 0, y: 0 } };
+    let mut sides = [empty; 2];
+    for (y, row) in board.board.iter().enumerate() {
+        for (x, square) in row.iter().enumerate() {
+            if let Square::Occupied(piece) = square {
+                let side = &mut sides[piece.color as usize];
+                side.counts[piece.kind as usize] += 1;
+                match piece.kind {
+                    PieceKind::Bishop if (x + y).is_multiple_of(2) => side.light_bishops += 1,
+                    PieceKind::Pawn => {
+                        side.pawn_files |= 1 << x;
+                        side.pawn_ranks |= 1 << y;
+                    }
+                    PieceKind::King => side.king = Coord { x, y },
+                    _ => {}
+                }
+            }
+        }
+    }
+    sides
+}
+
+fn distance(a: Coord, b: Coord) -> i32 {
+    a.x.abs_diff(b.x).max(a.y.abs_diff(b.y)) as i32
+}
+
+fn manhattan(a: Coord, b: Coord) -> i32 {
+    (a.x.abs_diff(b.x) + a.y.abs_diff(b.y)) as i32
+}
+
+/// Manhattan distance from the nearest of the four center squares, 0 to 6
+fn center_distance(square: Coord) -> i32 {
+    let from_center = |v: usize| if v < 4 { 3 - v } else { v - 4 };
+    (from_center(square.x) + from_center(square.y)) as i32
+}
+
+/// Bonus for the side mating a bare king: the defending king goes to the edge, or to a corner the
+/// bishop covers in KBNK, and the attacking king follows it there
+fn mop_up(strong: &Side, weak: &Side) -> i32 {
+    let mut bonus = KNOWN_WIN + PUSH_TO_EDGE * center_distance(weak.king) + PUSH_CLOSE * (7 - distance(strong.king, weak.king));
+    if strong.is_bishop_and_knight() {
+        let corners = if strong.light_bishops > 0 { [Coord { x: 0, y: 0 }, Coord { x: 7, y: 7 }] } else { [Coord { x: 7, y: 0 }, Coord { x: 0, y: 7 }] };
+        let corner_distance = corners.iter().map(|&corner| manhattan(weak.king, corner)).min().unwrap();
+        bonus += PUSH_TO_CORNER * (14 - corner_distance);
+    }
+    bonus
+}
+
+/// How much of the score the side ahead can hope to convert, out of [`SCALE_NORMAL`]
+fn scale_factor(strong: &Side, weak: &Side, strong_color: PieceColor) -> i32 {
+    let bishops_and_pawns = |side: &Side| side.count(PieceKind::Rook) == 0 && side.count(PieceKind::Queen) == 0 && side.count(PieceKind::Knight) == 0;
+
+    if strong.count(PieceKind::Pawn) == 0 {
+        // A lone minor or two knights can't mate
+        if strong.non_pawn_material() <= 3 || strong.non_pawn_material() == 6 && strong.count(PieceKind::Knight) == 2 {
+            return 0;
+        }
+        if strong.non_pawn_material() - weak.non_pawn_material() <= 3 {
+            return if weak.non_pawn_material() <= 3 { SCALE_MINOR_UP } else { SCALE_MINOR_UP_MAJORS };
+        }
+    }
+
+    // Rook pawns with no bishop or one that can't cover the promotion square are a draw once the
+    // defending king reaches the corner. So is a lone knight's rook pawn on the seventh, the knight can't
+    // drive the king out anymore.
+    let rook_file = match strong.pawn_files {
+        0x01 => Some(0),
+        0x80 => Some(7),
+        _ => None,
+    };
+    if let Some(x) = rook_file {
+        let promotion = Coord { x, y: if strong_color == PieceColor::White { 0 } else { 7 } };
+        let light_promotion = (promotion.x + promotion.y).is_multiple_of(2);
+        let wrong_bishop = match strong.count(PieceKind::Bishop) {
+            0 => true,
+            1 => (strong.light_bishops == 1) != light_promotion,
+            _ => false,
+        };
+        let seventh_rank = if strong_color == PieceColor::White { 1 } else { 6 };
+        let knight_on_seventh = strong.count(PieceKind::Knight) == 1
+            && strong.non_pawn_material() == 3
+            && strong.count(PieceKind::Pawn) == 1
+            && strong.pawn_ranks == 1 << seventh_rank;
+        if (bishops_and_pawns(strong) && wrong_bishop || knight_on_seventh) && weak.is_bare_king() && distance(weak.king, promotion) <= 1 {
+            return 0;
+        }
+    }
+
+    let opposite_bishops = strong.count(PieceKind::Bishop) == 1
+        && weak.count(PieceKind::Bishop) == 1
+        && strong.light_bishops != weak.light_bishops;
+    if opposite_bishops {
+        return if bishops_and_pawns(strong) && bishops_and_pawns(weak) { SCALE_OPPOSITE_BISHOPS } else { SCALE_OPPOSITE_BISHOPS_WITH_PIECES };
+    }
+    SCALE_NORMAL
+}
+
+/// Adjusts a White-relative evaluation for recognized endgames. Against a bare king the side that can
+/// force mate gets a gradient towards it, and endings that are drawn or drawish are scaled towards zero.
+pub fn evaluate_endgame(board: &Board, score: i32) -> i32 {
+    let [white, black] = sides(board);
+    if black.is_bare_king() && white.can_force_mate() {
+        return score + mop_up(&white, &black);
+    }
+    if white.is_bare_king() && black.can_force_mate() {
+        return score - mop_up(&black, &white);
+    }
+    let scale = match score {
+        0.. => scale_factor(&white, &black, PieceColor::White),
+        _ => scale_factor(&black, &white, PieceColor::Black),
+    };
+    score * scale / SCALE_NORMAL
+}
@@ -1,146 +1,54 @@
 use crate::board::{
     Board,
+    BoardError,
     Move,
     Coord,
-    // Result,
+    Square,
 };
 use crate::pieces::{
     PieceColor,
     PieceKind, Piece,
 };
+use crate::evaluation::{
+    piece_square_value,
+    Evaluator,
+    PstEvaluator,
+    PHASE_MAX,
+};
+use crate::nnue::{
+    NnueError,
+    NnueEvaluator,
+};
+use crate::params::{
+    EvalParams,
+    ParamsError,
+};
+use crate::book::{
+    Book,
+    BookError,
+    BookSelection,
+};
+use crate::retrograde::RetrogradeTablebase;
+use crate::skill::{
+    self,
+    Skill,
+};
+use crate::syzygy::{
+    self,
+    SyzygyError,
+    SyzygyTablebase,
+    Tablebase,
+    Wdl,
+};
+use rand::{rngs::StdRng, SeedableRng};
 use threadpool::ThreadPool;
-use std::sync::mpsc::channel;
-
-const WHITE_KING_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-20, -30, -30, -40, -40, -30, -30, -20],
-    [-10, -20, -20, -20, -20, -20, -20, -10],
-    [20, 20, 0, 0, 0, 0, 20, 20],
-    [20, 30, 10, 0, 0, 10, 30, 20],
-];
-
-const BLACK_KING_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [20, 30, 10, 0, 0, 10, 30, 20],
-    [20, 20, 0, 0, 0, 0, 20, 20],
-    [-10, -20, -20, -20, -20, -20, -20, -10],
-    [-20, -30, -30, -40, -40, -30, -30, -20],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-    [-30, -40, -40, -50, -50, -40, -40, -30],
-];
-
-const WHITE_QUEEN_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-20, -10, -10, -5, -5, -10, -10, -20],
-    [-10, 0, 0, 0, 0, 0, 0, -10],
-    [-10, 0, 5, 5, 5, 5, 0, -10],
-    [-5, 0, 5, 5, 5, 5, 0, -5],
-    [0, 0, 5, 5, 5, 5, 0, -5],
-    [-10, 5, 5, 5, 5, 5, 0, -10],
-    [-10, 0, 5, 0, 0, 0, 0, -10],
-    [-10, -00, -10, -5, -5, -5, -10, -20],
-];
-const BLACK_QUEEN_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-10, -00, -10, -5, -5, -5, -10, -20],
-    [-10, 0, 5, 0, 0, 0, 0, -10],
-    [-10, 5, 5, 5, 5, 5, 0, -10],
-    [0, 0, 5, 5, 5, 5, 0, -5],
-    [-5, 0, 5, 5, 5, 5, 0, -5],
-    [-10, 0, 5, 5, 5, 5, 0, -10],
-    [-10, 0, 0, 0, 0, 0, 0, -10],
-    [-20, -10, -10, -5, -5, -10, -10, -20],
-];
-
-const WHITE_ROOK_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [0, 0, 0, 0, 0, 0, 0, 0],
-    [5, 10, 10, 10, 10, 10, 10, 5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [0, 0, 0, 5, 5, 0, 0, 0],
-];
-
-const BLACK_ROOK_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [0, 0, 0, 5, 5, 0, 0, 0],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [-5, 0, 0, 0, 0, 0, 0, -5],
-    [5, 10, 10, 10, 10, 10, 10, 5],
-    [0, 0, 0, 0, 0, 0, 0, 0],
-];
-
-const WHITE_BISHOP_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-20, -10, -10, -10, -10, -10, -10, -20],
-    [-10, 0, 0, 0, 0, 0, 0, -10],
-    [-10, 0, 5, 10, 10, 5, 0, -10],
-    [-10, 5, 5, 10, 10, 5, 5, -10],
-    [-10, 0, 10, 10, 10, 10, 0, -10],
-    [-10, 10, 10, 10, 10, 10, 10, -10],
-    [-10, 5, 0, 0, 0, 0, 5, -10],
-    [-20, -10, -10, -10, -10, -10, -10, -20],
-];
-
-const BLACK_BISHOP_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-20, -10, -10, -10, -10, -10, -10, -20],
-    [-10, 5, 0, 0, 0, 0, 5, -10],
-    [-10, 10, 10, 10, 10, 10, 10, -10],
-    [-10, 0, 10, 10, 10, 10, 0, -10],
-    [-10, 5, 5, 10, 10, 5, 5, -10],
-    [-10, 0, 5, 10, 10, 5, 0, -10],
-    [-10, 0, 0, 0, 0, 0, 0, -10],
-    [-20, -10, -10, -10, -10, -10, -10, -20],
-];
-
-const WHITE_KNIGHT_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-50, -40, -30, -30, -30, -30, -40, -50],
-    [-40, -20, 0, 0, 0, 0, -20, -40],
-    [-30, 0, 10, 15, 15, 10, 0, -30],
-    [-30, 5, 15, 20, 20, 15, 5, -30],
-    [-30, 0, 15, 20, 20, 15, 0, -30],
-    [-30, 5, 10, 15, 15, 10, 5, -30],
-    [-40, -20, 0, 5, 5, 0, -20, -40],
-    [-50, -40, -30, -30, -30, -30, -40, -50],
-];
-
-const BLACK_KNIGHT_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [-50, -40, -30, -30, -30, -30, -40, -50],
-    [-40, -20, 0, 5, 5, 0, -20, -40],
-    [-30, 5, 10, 15, 15, 10, 5, -30],
-    [-30, 0, 15, 20, 20, 15, 0, -30],
-    [-30, 5, 15, 20, 20, 15, 5, -30],
-    [-30, 0, 10, 15, 15, 10, 0, -30],
-    [-40, -20, 0, 0, 0, 0, -20, -40],
-    [-50, -40, -30, -30, -30, -30, -40, -50],
-];
-
-const WHITE_PAWN_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [0, 0, 0, 0, 0, 0, 0, 0],
-    [50, 50, 50, 50, 50, 50, 50, 50],
-    [10, 10, 20, 30, 30, 20, 10, 10],
-    [5, 5, 10, 25, 25, 10, 5, 5],
-    [0, 0, 0, 20, 20, 0, 0, 0],
-    [5, -5, -10, 0, 0, -10, -5, 5],
-    [5, 15, -10, -20, -20, 10, 15, 5],
-    [0, 0, 0, 0, 0, 0, 0, 0],
-];
-
-const BLACK_PAWN_POSITION_WEIGHTS: [[i32; 8]; 8] = [
-    [0, 0, 0, 0, 0, 0, 0, 0],
-    [5, 15, -10, -20, -20, 10, 15, 5],
-    [5, -5, -10, 0, 0, -10, -5, 5],
-    [0, 0, 0, 20, 20, 0, 0, 0],
-    [5, 5, 10, 25, 25, 10, 5, 5],
-    [10, 10, 20, 30, 30, 20, 10, 10],
-    [50, 50, 50, 50, 50, 50, 50, 50],
-    [0, 0, 0, 0, 0, 0, 0, 0],
-];
+use std::sync::{
+    Arc,
+    Mutex,
+    atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering},
+};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 fn piece_score(piece: PieceKind) -> i32 {
     match piece {
@@ -153,177 +61,1720 @@ fn piece_score(piece: PieceKind) -> i32 {
     }
 }
 
+/// Score of a checkmate delivered at the root. Mates further away score `MATE - ply`.
+pub const MATE: i32 = 32_000;
+/// Deepest ply the search will ever reach; bounds the range of mate scores.
+pub const MAX_PLY: i32 = 128;
+/// Any score with an absolute value at or above this is a mate score.
+pub const MATE_BOUND: i32 = MATE - MAX_PLY;
+/// Larger than any score the search can return, used for the initial window.
+pub const INFINITY: i32 = MATE + 1;
+pub const DRAW: i32 = 0;
+/// Score of a tablebase win at the root, just below the mate scores. Wins found further away score `TB_WIN - ply`.
+pub const TB_WIN: i32 = MATE_BOUND - 1;
+/// Any score with an absolute value at or above this is a tablebase or mate score.
+pub const TB_BOUND: i32 = TB_WIN - MAX_PLY;
+
+/// Score for the side to move when it can mate in `ply` half-moves
+#[inline]
+pub fn mate_in(ply: i32) -> i32 {
+    MATE - ply
+}
+
+/// Score for the side to move when it gets mated in `ply` half-moves
+#[inline]
+pub fn mated_in(ply: i32) -> i32 {
+    -MATE + ply
+}
+
+#[inline]
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE_BOUND
+}
+
+/// Mate and tablebase scores are stored in the transposition table relative to the node instead of
+/// the root, so the same entry stays correct when it is reached at a different ply
+#[inline]
+pub(crate) fn score_to_tt(score: i32, ply: i32) -> i32 {
+    if score >= TB_BOUND {
+        score + ply
+    } else if score <= -TB_BOUND {
+        score - ply
+    } else {
+        score
+    }
+}
+
+#[inline]
+pub(crate) fn score_from_tt(score: i32, ply: i32) -> i32 {
+    if score >= TB_BOUND {
+        score - ply
+    } else if score <= -TB_BOUND {
+        score + ply
+    } else {
+        score
+    }
+}
+
+/// Material plus piece-square value of `piece` on `position` in the middlegame
+#[deprecated(note = "use evaluation::piece_square_value, which has the endgame value as well")]
 pub fn get_weighted_value(piece: Piece, position: Coord) -> i32 {
-    // println!("piece: {:?}, position: {:?}", piece, position);
-    let weights = match piece.kind {
-        PieceKind::King => match piece.color{
-            PieceColor::White => WHITE_KING_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_KING_POSITION_WEIGHTS,
-        },
-        PieceKind::Queen => match piece.color {
-            PieceColor::White => WHITE_QUEEN_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_QUEEN_POSITION_WEIGHTS,
-        },
-        PieceKind::Rook => match piece.color {
-            PieceColor::White => WHITE_ROOK_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_ROOK_POSITION_WEIGHTS,
-        },
-        PieceKind::Bishop => match piece.color {
-            PieceColor::White => WHITE_BISHOP_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_BISHOP_POSITION_WEIGHTS,
-        },
-        PieceKind::Knight => match piece.color {
-            PieceColor::White => WHITE_KNIGHT_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_KNIGHT_POSITION_WEIGHTS,
-        },
-        PieceKind::Pawn => match piece.color {
-            PieceColor::White => WHITE_PAWN_POSITION_WEIGHTS,
-            PieceColor::Black => BLACK_PAWN_POSITION_WEIGHTS,
-        },
+    piece_square_value(piece, position).blend(PHASE_MAX)
+}
+
+/// A search score in a form suitable for reporting
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Score {
+    /// Evaluation in centipawns from the point of view of the side to move
+    Centipawns(i32),
+    /// Mate in N full moves. Negative when the side to move is getting mated.
+    Mate(i32),
+}
+
+impl Score {
+    pub fn from_search(score: i32) -> Self {
+        if score >= MATE_BOUND {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score <= -MATE_BOUND {
+            Score::Mate(-(MATE + score) / 2)
+        } else {
+            Score::Centipawns(score)
+        }
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Score::Centipawns(cp) => write!(f, "cp {cp}"),
+            Score::Mate(n) => write!(f, "mate {n}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Bound {
+    Exact,
+    /// The real score is at least the stored score (fail high)
+    Lower,
+    /// The real score is at most the stored score (fail low)
+    Upper,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TTEntry {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+impl TTEntry {
+    // Layout: move (16 bits) | score (16 bits) | depth (8 bits) | bound (2 bits)
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        encode_move(self.best_move) as u64
+            | (self.score as i16 as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | bound << 40
+    }
+
+    fn unpack(data: u64) -> Self {
+        let bound = match (data >> 40) & 0b11 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        TTEntry {
+            best_move: decode_move(data as u16),
+            score: (data >> 16) as u16 as i16 as i32,
+            depth: (data >> 32) as u8,
+            bound,
+        }
+    }
+}
+
+fn encode_move(mv: Option<Move>) -> u16 {
+    match mv {
+        // A move always changes square so 0 (a8 to a8) can stand for no move
+        None => 0,
+        Some(mv) => {
+            let promote = match mv.promote {
+                None => 0,
+                Some(PieceKind::Knight) => 1,
+                Some(PieceKind::Bishop) => 2,
+                Some(PieceKind::Rook) => 3,
+                Some(_) => 4,
+            };
+            (mv.from.y * 8 + mv.from.x) as u16 | ((mv.to.y * 8 + mv.to.x) as u16) << 6 | promote << 12
+        }
+    }
+}
+
+fn decode_move(data: u16) -> Option<Move> {
+    if data == 0 {
+        return None;
+    }
+    let from = (data & 0x3f) as usize;
+    let to = ((data >> 6) & 0x3f) as usize;
+    let promote = match data >> 12 {
+        0 => None,
+        1 => Some(PieceKind::Knight),
+        2 => Some(PieceKind::Bishop),
+        3 => Some(PieceKind::Rook),
+        _ => Some(PieceKind::Queen),
     };
-    weights[position.y][position.x] + (piece_score(piece.kind))
+    Some(Move::new(Coord { x: from % 8, y: from / 8 }, Coord { x: to % 8, y: to / 8 }, promote))
 }
 
-pub fn make_best_move(depth: u8, board: &Board) -> Option<Move> {
-    let possible_moves = board.list_all_valid_moves();
-    if possible_moves.is_empty() {
-        return None
+/// Lockless transposition table. Each slot holds the key xor'ed with the data, so a slot torn by
+/// concurrent writers fails verification instead of returning another position's data.
+pub struct TranspositionTable {
+    slots: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let slot_size = std::mem::size_of::<(AtomicU64, AtomicU64)>();
+        let len = ((size_mb.max(1) * 1024 * 1024) / slot_size).next_power_of_two() / 2;
+        let mut slots = Vec::with_capacity(len);
+        slots.resize_with(len, || (AtomicU64::new(0), AtomicU64::new(0)));
+        TranspositionTable { slots }
     }
 
-    let mut best_move = Move{from: Coord{x: 0, y: 0}, to: Coord{x: 0, y: 0}, promote: None};
-    let mut best_score = i32::MIN;
+    #[inline]
+    fn slot(&self, key: u64) -> &(AtomicU64, AtomicU64) {
+        &self.slots[(key as usize) & (self.slots.len() - 1)]
+    }
 
-    let n_jobs = possible_moves.len();
-    let n_workers = std::thread::available_parallelism().unwrap().get();
-    let pool = ThreadPool::new(n_workers);
+    pub fn probe(&self, key: u64) -> Option<TTEntry> {
+        let (check, data) = self.slot(key);
+        let data = data.load(AtomicOrdering::Relaxed);
+        if data != 0 && check.load(AtomicOrdering::Relaxed) ^ data == key {
+            Some(TTEntry::unpack(data))
+        } else {
+            None
+        }
+    }
 
-    let (tx, rx) = channel();
+    pub fn store(&self, key: u64, entry: TTEntry) {
+        let (check, data) = self.slot(key);
+        let old = data.load(AtomicOrdering::Relaxed);
+        let same_position = check.load(AtomicOrdering::Relaxed) ^ old == key;
+        // Keep deeper results for the same position unless the new one is exact
+        if same_position && entry.bound != Bound::Exact && TTEntry::unpack(old).depth > entry.depth {
+            return;
+        }
+        let new = entry.pack();
+        check.store(key ^ new, AtomicOrdering::Relaxed);
+        data.store(new, AtomicOrdering::Relaxed);
+    }
 
-    for m in possible_moves {
-        let getting_move_for = board.turn;
-        let mut board = *board;
-        board.do_move_from_coord(m).unwrap();
-        let tx = tx.clone();
-        pool.execute(move || {
-            let score = minimax(&board, depth, i32::MIN, i32::MAX, false, getting_move_for);
-            // println!("{:?}: {}", m, score);
-            tx.send((m, score)).unwrap();
-        });
+    pub fn clear(&self) {
+        for (check, data) in &self.slots {
+            check.store(0, AtomicOrdering::Relaxed);
+            data.store(0, AtomicOrdering::Relaxed);
+        }
+    }
+}
+
+const TT_SIZE_MB: usize = 16;
+
+/// Switches for the selective parts of the search, mostly useful to measure what each one is worth
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SearchOptions {
+    /// Resolve captures at the horizon instead of returning the static evaluation
+    pub quiescence: bool,
+    /// Give the opponent a free move and prune if the position still fails high
+    pub null_move: bool,
+    /// Search late quiet moves to a reduced depth first
+    pub late_move_reductions: bool,
+    /// Skip quiet moves near the horizon when the static evaluation is far below alpha
+    pub futility_pruning: bool,
+    /// Drop straight into quiescence near the horizon when the static evaluation is hopeless
+    pub razoring: bool,
+    /// Search the moves after the first with a null window, and again with the full window only if one beats it
+    pub principal_variation_search: bool,
+    /// Start each iteration with a narrow window around the previous score, widening it when the score falls outside
+    pub aspiration_windows: bool,
+    /// Search checks, pawn pushes to the seventh rank, recaptures and singular moves a ply deeper
+    pub extensions: bool,
+    /// Probe the tablebases, if any, in positions with at most this many pieces
+    pub syzygy_probe_limit: usize,
+    /// Number of best root moves to find, each with its own line. Every line after the first costs about
+    /// as much as another search.
+    pub multi_pv: usize,
+    /// Centipawns a draw is worth less than an even position to the side to move at the root, so a positive
+    /// value avoids repetitions and other draws against a weaker opponent and a negative one seeks them
+    pub contempt: i32,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            quiescence: true,
+            null_move: true,
+            late_move_reductions: true,
+            futility_pruning: true,
+            razoring: true,
+            principal_variation_search: true,
+            aspiration_windows: true,
+            extensions: true,
+            syzygy_probe_limit: syzygy::MAX_PIECES,
+            multi_pv: 1,
+            contempt: 0,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Plain alpha-beta with none of the selective features
+    pub fn full_width() -> Self {
+        SearchOptions {
+            quiescence: false,
+            null_move: false,
+            late_move_reductions: false,
+            futility_pruning: false,
+            razoring: false,
+            principal_variation_search: false,
+            aspiration_windows: false,
+            extensions: false,
+            syzygy_probe_limit: 0,
+            multi_pv: 1,
+            contempt: 0,
+        }
+    }
+}
+
+const FUTILITY_MARGIN: i32 = 150;
+/// Half the width of the first aspiration window, doubled on every fail high or low
+const ASPIRATION_WINDOW: i32 = 30;
+/// Scores of shallower iterations are too unstable to aim a window with
+const ASPIRATION_MIN_DEPTH: u8 = 4;
+/// Shallowest remaining depth to look for a singular move at, the verification search isn't cheap
+const SINGULAR_MIN_DEPTH: i32 = 6;
+/// Per ply of depth, how far every other move has to stay below the hash move's score for it to count as singular
+const SINGULAR_MARGIN: i32 = 2;
+/// Largest contempt the `Contempt` option accepts, more would have the engine prefer losing material to a draw
+pub const MAX_CONTEMPT: i32 = 500;
+const RAZOR_MARGIN: i32 = 300;
+/// Below this much non-pawn material zugzwang is likely enough that null-move cutoffs get verified
+const NULL_MOVE_VERIFY_MATERIAL: i32 = 500;
+
+/// Bounds on how long a search may run. Every limit that is set applies, the search ends at the first one reached.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SearchLimits {
+    /// Maximum depth in plies from the root
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    /// Exact time to spend on this move
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    /// Moves left until the next time control, sudden death if not set
+    pub movestogo: Option<u32>,
+    /// Stop as soon as a mate in this many moves (or fewer) is found
+    pub mate: Option<u32>,
+    /// Ignore every limit and search until stopped
+    pub infinite: bool,
+}
+
+/// Time reserved for communication and move making when playing on the clock
+const MOVE_OVERHEAD: Duration = Duration::from_millis(20);
+/// Assumed moves left in sudden death games
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+impl SearchLimits {
+    pub fn to_depth(depth: u8) -> Self {
+        SearchLimits { depth: Some(depth), ..Default::default() }
+    }
+
+    pub fn for_time(movetime: Duration) -> Self {
+        SearchLimits { movetime: Some(movetime), ..Default::default() }
+    }
+
+    pub fn infinite() -> Self {
+        SearchLimits { infinite: true, ..Default::default() }
+    }
+
+    fn max_depth(&self) -> u8 {
+        let max = MAX_PLY as u8 - 1;
+        if self.infinite {
+            return max;
+        }
+        let mate_depth = self.mate.map(|n| (2 * n).saturating_sub(1).clamp(1, max as u32) as u8);
+        match (self.depth, mate_depth) {
+            (Some(depth), Some(mate_depth)) => depth.min(mate_depth),
+            (Some(depth), None) => depth,
+            (None, Some(mate_depth)) => mate_depth,
+            (None, None) => max,
+        }.clamp(1, max)
+    }
+
+    /// Soft and hard time limits for the side to move. The search won't start a new iteration after
+    /// the soft limit and aborts at the hard limit.
+    pub fn time_budget(&self, color: PieceColor) -> Option<(Duration, Duration)> {
+        if self.infinite {
+            return None;
+        }
+        if let Some(movetime) = self.movetime {
+            return Some((movetime, movetime));
+        }
+        let (time, increment) = match color {
+            PieceColor::White => (self.wtime?, self.winc.unwrap_or_default()),
+            PieceColor::Black => (self.btime?, self.binc.unwrap_or_default()),
+        };
+        let available = time.saturating_sub(MOVE_OVERHEAD);
+        let moves_to_go = self.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let hard = available / 2;
+        let soft = (available / moves_to_go + increment * 3 / 4).min(hard);
+        Some((soft, (soft * 3).min(hard)))
+    }
+}
+
+/// Flag to abort a running search from another thread. Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stop(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
     }
 
-    for _ in 0..n_jobs {
-        let (m, score) = rx.recv().unwrap();
-        if score > best_score {
-            best_score = score;
-            best_move = m;
+    pub fn reset(&self) {
+        self.0.store(false, AtomicOrdering::Relaxed);
+    }
+}
+
+/// Nodes a thread searches between checks of the limits
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Limits shared by every thread of one search
+struct SearchControl {
+    stop: StopSignal,
+    /// Set when a limit is reached, kept apart from `stop` so it doesn't leak into the caller's signal
+    aborted: AtomicBool,
+    start: Instant,
+    soft_deadline: Option<Duration>,
+    hard_deadline: Option<Duration>,
+    node_limit: Option<u64>,
+    nodes: AtomicU64,
+}
+
+impl SearchControl {
+    fn new(board: &Board, limits: &SearchLimits, stop: StopSignal) -> Self {
+        let budget = limits.time_budget(board.turn);
+        SearchControl {
+            stop,
+            aborted: AtomicBool::new(false),
+            start: Instant::now(),
+            soft_deadline: budget.map(|(soft, _)| soft),
+            hard_deadline: budget.map(|(_, hard)| hard),
+            node_limit: if limits.infinite { None } else { limits.nodes },
+            nodes: AtomicU64::new(0),
         }
     }
-    // println!("Best move: {:?} with score {}", best_move, best_score);
-    Some(best_move)        
+
+    fn stopped(&self) -> bool {
+        self.aborted.load(AtomicOrdering::Relaxed) || self.stop.is_stopped()
+    }
+
+    /// Adds a batch of nodes to the shared count and reports whether the search has to end
+    fn check(&self, new_nodes: u64) -> bool {
+        let nodes = self.nodes.fetch_add(new_nodes, AtomicOrdering::Relaxed) + new_nodes;
+        let out_of_nodes = self.node_limit.is_some_and(|limit| nodes >= limit);
+        let out_of_time = self.hard_deadline.is_some_and(|deadline| self.start.elapsed() >= deadline);
+        if out_of_nodes || out_of_time {
+            self.aborted.store(true, AtomicOrdering::Relaxed);
+        }
+        self.stopped()
+    }
+
+    /// Ends the search for every thread
+    fn finish(&self) {
+        self.aborted.store(true, AtomicOrdering::Relaxed);
+    }
+
+    fn past_soft_deadline(&self) -> bool {
+        self.soft_deadline.is_some_and(|deadline| self.start.elapsed() >= deadline)
+    }
+}
+
+/// A root move with the line the search expects to follow it
+#[derive(Clone, PartialEq, Debug)]
+pub struct PrincipalVariation {
+    /// Starts with the root move, never empty
+    pub moves: Vec<Move>,
+    /// Score from the point of view of the side to move, see [`Score::from_search`]
+    pub score: i32,
+}
+
+impl PrincipalVariation {
+    pub fn score(&self) -> Score {
+        Score::from_search(self.score)
+    }
 }
 
+#[derive(Clone, PartialEq, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// Score from the point of view of the side to move, see [`Score::from_search`]
+    pub score: i32,
+    /// Depth of the last completed iteration
+    pub depth: u8,
+    pub nodes: u64,
+    /// Best lines first, as many as [`SearchOptions::multi_pv`] asks for and there are legal moves. The first
+    /// starts with `best_move` and has its score.
+    pub lines: Vec<PrincipalVariation>,
+}
+
+impl SearchResult {
+    pub fn score(&self) -> Score {
+        Score::from_search(self.score)
+    }
+}
 
-fn minimax(
+/// Searches `depth` plies below each root move, so the full search is `depth + 1` plies deep
+pub fn make_best_move(depth: u8, board: &Board) -> Option<Move> {
+    search_best_move(depth, board).map(|result| result.best_move)
+}
+
+pub fn search_best_move(depth: u8, board: &Board) -> Option<SearchResult> {
+    search_with_options(depth, board, SearchOptions::default())
+}
+
+pub fn search_with_options(depth: u8, board: &Board, options: SearchOptions) -> Option<SearchResult> {
+    search(board, &SearchLimits::to_depth(depth + 1), options, &StopSignal::new())
+}
+
+/// Single-threaded search bounded by `limits`. Given the same position, limits and options the result
+/// is always the same. See [`SearchPool`] for a parallel search, and one that knows the game history.
+pub fn search(board: &Board, limits: &SearchLimits, options: SearchOptions, stop: &StopSignal) -> Option<SearchResult> {
+    search_with_evaluator(board, limits, options, stop, Box::new(PstEvaluator::new()))
+}
+
+/// [`search`] using `evaluator` instead of the built-in evaluation
+pub fn search_with_evaluator(
     board: &Board,
-    depth: u8,
-    mut alpha: i32,
-    mut beta: i32,
-    is_maximizing: bool,
-    getting_move_for: PieceColor,
-) -> i32 {
+    limits: &SearchLimits,
+    options: SearchOptions,
+    stop: &StopSignal,
+    evaluator: Box<dyn Evaluator>,
+) -> Option<SearchResult> {
+    let tt = TranspositionTable::new(TT_SIZE_MB);
+    let control = SearchControl::new(board, limits, stop.clone());
+    let mut state = ThreadState::new(evaluator);
+    Searcher::new(&tt, &control, options, &mut state, None, &[]).iterative_deepening(board, limits, 0)
+}
+
+fn default_thread_count() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Lazy SMP: every thread runs its own iterative deepening on the same root, and they cooperate only
+/// through the shared transposition table. The threads, table and move ordering tables are kept between searches.
+pub struct SearchPool {
+    /// Helper threads, the calling thread acts as the main search thread
+    helpers: Option<ThreadPool>,
+    tt: Arc<TranspositionTable>,
+    /// One per thread, index 0 belongs to the main thread
+    states: Vec<Arc<Mutex<Box<ThreadState>>>>,
+    /// Every thread evaluates with a fork of this one
+    evaluator: Box<dyn Evaluator>,
+    tablebase: Option<Arc<dyn Tablebase>>,
+}
+
+impl SearchPool {
+    /// `threads` counts the calling thread, 0 uses every available core
+    pub fn new(threads: usize, tt_size_mb: usize) -> Self {
+        let mut pool = SearchPool {
+            helpers: None,
+            tt: Arc::new(TranspositionTable::new(tt_size_mb)),
+            states: Vec::new(),
+            evaluator: Box::new(PstEvaluator::new()),
+            tablebase: None,
+        };
+        pool.set_threads(threads);
+        pool
+    }
+
+    pub fn threads(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        let threads = if threads == 0 { default_thread_count() } else { threads };
+        self.helpers = match threads {
+            1 => None,
+            n => Some(ThreadPool::new(n - 1)),
+        };
+        let evaluator = &self.evaluator;
+        self.states.resize_with(threads, || Arc::new(Mutex::new(ThreadState::new(evaluator.fork()))));
+    }
+
+    /// Evaluates with `evaluator` from the next search on, every thread gets its own fork of it
+    pub fn set_evaluator(&mut self, evaluator: Box<dyn Evaluator>) {
+        for state in &self.states {
+            state.lock().unwrap().evaluator = evaluator.fork();
+        }
+        self.evaluator = evaluator;
+    }
 
-    
-    if depth == 0 {
-        return eval_position(board, getting_move_for);
+    /// Endgame tablebases to consult from the next search on, `None` for none
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<dyn Tablebase>>) {
+        self.tablebase = tablebase;
     }
 
-    let legal_moves = board.list_all_valid_moves();
-    let mut best_move_value;
+    pub fn resize_tt(&mut self, tt_size_mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(tt_size_mb));
+    }
 
-    // If there are no legal moves, return the evaluation to distinguish between checkmate and stalemate
-    if legal_moves.is_empty() {
-        return eval_position(board, getting_move_for);
+    /// Forgets everything learned in earlier searches
+    pub fn clear(&self) {
+        self.tt.clear();
+        for state in &self.states {
+            state.lock().unwrap().clear();
+        }
     }
 
-    if is_maximizing {
-        best_move_value = i32::MIN+1;
+    /// With one thread this is as deterministic as [`search`], apart from what the tables remember from earlier searches.
+    /// `history` holds the [`Board::zobrist_key`]s of the positions played before `board`, oldest first, so moves
+    /// that repeat one of them are scored as draws.
+    pub fn search(
+        &self,
+        board: &Board,
+        history: &[u64],
+        limits: &SearchLimits,
+        options: SearchOptions,
+        stop: &StopSignal,
+    ) -> Option<SearchResult> {
+        let control = Arc::new(SearchControl::new(board, limits, stop.clone()));
+        let history: Arc<[u64]> = history.into();
 
-        for m in legal_moves {
-            let mut board = *board;
-            board.do_move_from_coord(m).unwrap();
-            // Subtracts 1 for each level of recursion to prefer shorter sequences with the same result (e.g. mate in 1 vs mate in 2)
-            let child_board_value = minimax(&board, depth - 1, alpha, beta, !is_maximizing, getting_move_for) - 1;
-            
-            if child_board_value > best_move_value {
-                best_move_value = child_board_value;
+        if let Some(helpers) = &self.helpers {
+            for (id, state) in self.states.iter().enumerate().skip(1) {
+                let board = *board;
+                let limits = *limits;
+                let tt = Arc::clone(&self.tt);
+                let control = Arc::clone(&control);
+                let state = Arc::clone(state);
+                let tablebase = self.tablebase.clone();
+                let history = Arc::clone(&history);
+                helpers.execute(move || {
+                    let mut state = state.lock().unwrap();
+                    Searcher::new(&tt, &control, options, &mut state, tablebase.as_deref(), &history).iterative_deepening(&board, &limits, id);
+                });
             }
+        }
+
+        let result = {
+            let mut state = self.states[0].lock().unwrap();
+            Searcher::new(&self.tt, &control, options, &mut state, self.tablebase.as_deref(), &history).iterative_deepening(board, limits, 0)
+        };
+        // Helpers run until told otherwise, and must be done before the next search starts
+        control.finish();
+        if let Some(helpers) = &self.helpers {
+            helpers.join();
+        }
+        result.map(|result| SearchResult { nodes: control.nodes.load(AtomicOrdering::Relaxed), ..result })
+    }
+}
+
+impl Default for SearchPool {
+    fn default() -> Self {
+        SearchPool::new(0, TT_SIZE_MB)
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// The engine is busy, stop or wait for the running search first
+    SearchInProgress,
+    UnknownOption(String),
+    InvalidOptionValue { name: String, value: String },
+    /// The starting position or one of the moves played from it is invalid
+    Position(BoardError),
+    /// The evaluation parameter file couldn't be loaded
+    Params(ParamsError),
+    /// The network file couldn't be loaded
+    Network(NnueError),
+    /// The opening book couldn't be loaded
+    Book(BookError),
+    /// The tablebase directory couldn't be read
+    Tablebase(SyzygyError),
+    /// The search thread couldn't be started or died
+    Thread(String),
+}
+pub type Result<T> = std::result::Result<T, EngineError>;
+
+impl std::error::Error for EngineError {}
 
-            if best_move_value > alpha {
-                alpha = best_move_value
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EngineError::SearchInProgress => write!(f, "A search is already in progress"),
+            EngineError::UnknownOption(name) => write!(f, "Unknown option: {name}"),
+            EngineError::InvalidOptionValue { name, value } => write!(f, "Invalid value for option {name}: {value}"),
+            EngineError::Position(err) => write!(f, "Invalid position: {err}"),
+            EngineError::Params(err) => write!(f, "{err}"),
+            EngineError::Network(err) => write!(f, "{err}"),
+            EngineError::Book(err) => write!(f, "{err}"),
+            EngineError::Tablebase(err) => write!(f, "{err}"),
+            EngineError::Thread(desc) => write!(f, "Search thread error: {desc}"),
+        }
+    }
+}
+
+impl From<BoardError> for EngineError {
+    fn from(err: BoardError) -> Self {
+        EngineError::Position(err)
+    }
+}
+
+impl From<ParamsError> for EngineError {
+    fn from(err: ParamsError) -> Self {
+        EngineError::Params(err)
+    }
+}
+
+impl From<NnueError> for EngineError {
+    fn from(err: NnueError) -> Self {
+        EngineError::Network(err)
+    }
+}
+
+impl From<BookError> for EngineError {
+    fn from(err: BookError) -> Self {
+        EngineError::Book(err)
+    }
+}
+
+impl From<SyzygyError> for EngineError {
+    fn from(err: SyzygyError) -> Self {
+        EngineError::Tablebase(err)
+    }
+}
+
+/// Long-lived engine instance for front-ends. It keeps its threads, transposition table and move
+/// ordering tables between moves, and searches in the background so it can be stopped.
+pub struct Engine {
+    pool: Arc<Mutex<SearchPool>>,
+    /// Threads of the pool, kept here so asking doesn't wait for the search that holds the pool
+    threads: usize,
+    options: SearchOptions,
+    position: Board,
+    /// Keys of the positions played before `position`, see [`SearchPool::search`]
+    history: Vec<u64>,
+    stop: StopSignal,
+    search: Option<JoinHandle<Option<SearchResult>>>,
+    book: Option<Arc<Book>>,
+    book_selection: BookSelection,
+    skill: Skill,
+    /// Picks book moves and the moves played below full strength
+    rng: StdRng,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let pool = SearchPool::default();
+        Engine {
+            threads: pool.threads(),
+            pool: Arc::new(Mutex::new(pool)),
+            options: SearchOptions::default(),
+            position: Board::default(),
+            history: Vec::new(),
+            stop: StopSignal::new(),
+            search: None,
+            book: None,
+            book_selection: BookSelection::default(),
+            skill: Skill::default(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Fails while a search is running. A search that has finished counts as idle, a result that wasn't
+    /// collected with [`Engine::wait`] is dropped.
+    fn ensure_idle(&mut self) -> Result<()> {
+        if self.is_searching() {
+            return Err(EngineError::SearchInProgress);
+        }
+        if let Some(handle) = self.search.take() {
+            // A panic there is of no interest anymore, the next search starts afresh
+            let _ = handle.join();
+        }
+        Ok(())
+    }
+
+    /// Resets to the starting position and forgets everything learned in previous games
+    pub fn new_game(&mut self) -> Result<()> {
+        self.ensure_idle()?;
+        self.pool.lock().unwrap().clear();
+        self.position = Board::default();
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Sets the position to search as `start` followed by `moves`. The search avoids or seeks repetitions of
+    /// the positions along the way depending on the contempt.
+    pub fn set_position(&mut self, start: Board, moves: &[Move]) -> Result<()> {
+        self.ensure_idle()?;
+        let mut board = start;
+        let mut history = Vec::with_capacity(moves.len());
+        for &mv in moves {
+            history.push(board.zobrist_key());
+            board.do_move_from_coord(mv)?;
+        }
+        self.position = board;
+        self.history = history;
+        Ok(())
+    }
+
+    pub fn position(&self) -> &Board {
+        &self.position
+    }
+
+    pub fn options(&self) -> SearchOptions {
+        self.options
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn skill(&self) -> Skill {
+        self.skill
+    }
+
+    /// Plays at `skill` from the next search on
+    pub fn set_skill(&mut self, skill: Skill) -> Result<()> {
+        self.ensure_idle()?;
+        self.skill = skill;
+        Ok(())
+    }
+
+    /// Makes book and skill choices repeat: the same seed, positions and searches give the same moves
+    pub fn seed(&mut self, seed: u64) -> Result<()> {
+        self.ensure_idle()?;
+        self.rng = StdRng::seed_from_u64(seed);
+        Ok(())
+    }
+
+    /// Replaces the opening book, `None` to play without one
+    pub fn set_book(&mut self, book: Option<Book>) -> Result<()> {
+        self.ensure_idle()?;
+        self.book = book.map(Arc::new);
+        Ok(())
+    }
+
+    /// Starts searching the current position in the background. Collect the result with [`Engine::wait`]
+    /// before changing the position or settings, which drops it once the search is done.
+    /// Positions found in the opening book aren't searched, the book move comes back with depth and nodes 0.
+    /// Below full strength the limits are tightened as the [`Skill`] demands.
+    pub fn go(&mut self, limits: SearchLimits) -> Result<()> {
+        self.ensure_idle()?;
+        self.stop.reset();
+        let pool = Arc::clone(&self.pool);
+        let board = self.position;
+        let history = self.history.clone();
+        let limits = self.skill.limits(limits);
+        let options = SearchOptions { multi_pv: self.options.multi_pv.max(self.skill.multi_pv()), ..self.options };
+        let stop = self.stop.clone();
+        let book_move = self.book.as_ref().and_then(|book| book.choose(&board, self.book_selection, &mut self.rng));
+        let handle = std::thread::Builder::new()
+            .name("search".to_string())
+            .spawn(move || match book_move {
+                Some(best_move) => Some(SearchResult {
+                    best_move,
+                    score: DRAW,
+                    depth: 0,
+                    nodes: 0,
+                    lines: vec![PrincipalVariation { moves: vec![best_move], score: DRAW }],
+                }),
+                None => pool.lock().unwrap().search(&board, &history, &limits, options, &stop),
+            })
+            .map_err(|err| EngineError::Thread(err.to_string()))?;
+        self.search = Some(handle);
+        Ok(())
+    }
+
+    /// Asks the running search to finish as soon as possible, it still has to be collected with [`Engine::wait`]
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+
+    /// Signal that stops the engine's searches, for use from other threads
+    pub fn stop_signal(&self) -> StopSignal {
+        self.stop.clone()
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Blocks until the running search ends and returns its result. `None` if there are no legal moves.
+    /// Below full strength `best_move` is the move the [`Skill`] chose to play, with the score of its line if it has one.
+    pub fn wait(&mut self) -> Result<Option<SearchResult>> {
+        let handle = self.search.take().ok_or_else(|| EngineError::Thread("No search is running".to_string()))?;
+        let mut result = handle.join().map_err(|_| EngineError::Thread("Search thread panicked".to_string()))?;
+        if let Some(result) = result.as_mut() {
+            result.best_move = self.skill.choose(&self.position, result, &mut self.rng);
+            if let Some(line) = result.lines.iter().find(|line| line.moves[0] == result.best_move) {
+                result.score = line.score;
             }
+        }
+        Ok(result)
+    }
+
+    /// Searches the current position and blocks until done
+    pub fn search(&mut self, limits: SearchLimits) -> Result<Option<SearchResult>> {
+        self.go(limits)?;
+        self.wait()
+    }
+
+    /// Replaces the endgame tablebases, `None` to search without
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<dyn Tablebase>>) -> Result<()> {
+        self.ensure_idle()?;
+        self.pool.lock().unwrap().set_tablebase(tablebase);
+        Ok(())
+    }
 
-            if beta <= alpha {
-                return best_move_value;
+    /// Replaces the evaluation function used by all search threads
+    pub fn set_evaluator(&mut self, evaluator: Box<dyn Evaluator>) -> Result<()> {
+        self.ensure_idle()?;
+        self.pool.lock().unwrap().set_evaluator(evaluator);
+        Ok(())
+    }
+
+    /// Changes an option by its (case insensitive) name: `Threads`, `Hash` (in MB), `EvalFile` (a parameter
+    /// file for the built-in evaluation, see [`EvalParams`]), `NnueFile` (switches to a network, see
+    /// [`crate::nnue::Network`]), `BookFile` (a Polyglot opening book, empty for none), `BookSelection` (`best`
+    /// or `weighted`), `SyzygyPath` (a directory of Syzygy tablebases, empty for none), `EndgameTables` (material
+    /// signatures such as `KRvK,KBNvK` to generate, see [`RetrogradeTablebase`], replacing any Syzygy tablebases),
+    /// `SyzygyProbeLimit` (most pieces to probe with), `MultiPV` (number of lines to report), `SkillLevel` (0 to
+    /// [`skill::MAX_LEVEL`], the maximum plays at full strength), `Elo` (the skill level closest to this rating),
+    /// `Seed` (see [`Engine::seed`]), `Contempt` (see [`SearchOptions::contempt`]), or one of the search switches `Quiescence`, `NullMove`, `LateMoveReductions`,
+    /// `FutilityPruning`, `Razoring`, `PrincipalVariationSearch`, `AspirationWindows` and `Extensions`
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        self.ensure_idle()?;
+        let invalid = || EngineError::InvalidOptionValue { name: name.to_string(), value: value.to_string() };
+        let parse_bool = || match value.to_ascii_lowercase().as_str() {
+            "true" | "on" | "1" => Ok(true),
+            "false" | "off" | "0" => Ok(false),
+            _ => Err(invalid()),
+        };
+        let parse_size = || match value.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(invalid()),
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "threads" => {
+                let mut pool = self.pool.lock().unwrap();
+                pool.set_threads(parse_size()?);
+                self.threads = pool.threads();
+            }
+            "hash" => self.pool.lock().unwrap().resize_tt(parse_size()?),
+            "evalfile" => {
+                let params = EvalParams::load(value)?;
+                self.pool.lock().unwrap().set_evaluator(Box::new(PstEvaluator::with_params(params)));
+            }
+            "nnuefile" => {
+                let evaluator = NnueEvaluator::load(value)?;
+                self.pool.lock().unwrap().set_evaluator(Box::new(evaluator));
             }
+            "bookfile" => self.book = if value.is_empty() { None } else { Some(Arc::new(Book::load(value)?)) },
+            "bookselection" => {
+                self.book_selection = match value.to_ascii_lowercase().as_str() {
+                    "best" => BookSelection::Best,
+                    "weighted" => BookSelection::Weighted,
+                    _ => return Err(invalid()),
+                }
+            }
+            "syzygypath" => {
+                let tablebase = match value {
+                    "" => None,
+                    dir => Some(Arc::new(SyzygyTablebase::open(dir)?) as Arc<dyn Tablebase>),
+                };
+                self.pool.lock().unwrap().set_tablebase(tablebase);
+            }
+            "endgametables" => {
+                let mut tablebase = RetrogradeTablebase::new();
+                for name in value.split([',', ' ']).filter(|name| !name.is_empty()) {
+                    tablebase.generate(name).map_err(|_| invalid())?;
+                }
+                let tablebase = (tablebase.max_pieces() > 0).then(|| Arc::new(tablebase) as Arc<dyn Tablebase>);
+                self.pool.lock().unwrap().set_tablebase(tablebase);
+            }
+            "syzygyprobelimit" => {
+                self.options.syzygy_probe_limit = match value.parse() {
+                    Ok(n) if n <= syzygy::MAX_PIECES => n,
+                    _ => return Err(invalid()),
+                }
+            }
+            "multipv" => self.options.multi_pv = parse_size()?,
+            "contempt" => {
+                self.options.contempt = match value.parse::<i32>() {
+                    Ok(contempt) if contempt.abs() <= MAX_CONTEMPT => contempt,
+                    _ => return Err(invalid()),
+                }
+            }
+            "skilllevel" => {
+                self.skill = match value.parse() {
+                    Ok(level) if level <= skill::MAX_LEVEL => Skill::new(level),
+                    _ => return Err(invalid()),
+                }
+            }
+            "elo" => self.skill = Skill::from_elo(value.parse().map_err(|_| invalid())?),
+            "seed" => self.rng = StdRng::seed_from_u64(value.parse().map_err(|_| invalid())?),
+            "quiescence" => self.options.quiescence = parse_bool()?,
+            "nullmove" => self.options.null_move = parse_bool()?,
+            "latemovereductions" => self.options.late_move_reductions = parse_bool()?,
+            "futilitypruning" => self.options.futility_pruning = parse_bool()?,
+            "razoring" => self.options.razoring = parse_bool()?,
+            "principalvariationsearch" => self.options.principal_variation_search = parse_bool()?,
+            "aspirationwindows" => self.options.aspiration_windows = parse_bool()?,
+            "extensions" => self.options.extensions = parse_bool()?,
+            _ => return Err(EngineError::UnknownOption(name.to_string())),
         }
-    } else {
-        best_move_value = i32::MAX-1;
+        Ok(())
+    }
+}
 
-        for m in legal_moves {
-            let mut board = *board;
-            board.do_move_from_coord(m).unwrap();
-            // Subtracts 1 for each level of recursion to prefer shorter sequences with the same result (e.g. mate in 1 vs mate in 2)
-            let child_board_value = minimax(&board, depth - 1, alpha, beta, !is_maximizing, getting_move_for) - 1;
+#[inline]
+pub(crate) fn is_capture(board: &Board, mv: Move) -> bool {
+    match board.piece_at(mv.from) {
+        // Pawns only change file when capturing, which also covers en passant
+        Some(Piece { kind: PieceKind::Pawn, .. }) => mv.from.x != mv.to.x,
+        _ => board.piece_at(mv.to).is_some(),
+    }
+}
+
+#[inline]
+fn is_promotion(board: &Board, mv: Move) -> bool {
+    matches!(board.piece_at(mv.from), Some(Piece { kind: PieceKind::Pawn, .. })) && (mv.to.y == 0 || mv.to.y == 7)
+}
 
-            if child_board_value < best_move_value {
-                best_move_value = child_board_value;
+fn non_pawn_material(board: &Board, color: PieceColor) -> i32 {
+    let mut material = 0;
+    for row in board.board.iter() {
+        for square in row.iter() {
+            if let Square::Occupied(piece) = square {
+                if piece.color == color && piece.kind != PieceKind::Pawn && piece.kind != PieceKind::King {
+                    material += piece_score(piece.kind);
+                }
             }
+        }
+    }
+    material
+}
+
+/// How many plies to take off a late quiet move, growing with both depth and move number
+fn late_move_reduction(depth: i32, move_index: usize) -> i32 {
+    let r = 0.75 + (depth as f64).ln() * (move_index as f64).ln() / 2.25;
+    (r as i32).clamp(1, depth - 1)
+}
+
+/// Move ordering tables and evaluator of one search thread, kept from one search to the next
+struct ThreadState {
+    /// Two quiet moves per ply that recently caused a beta cutoff
+    killers: [[Option<Move>; 2]; MAX_PLY as usize + 1],
+    /// Cutoff counts of quiet moves indexed by color, from square and to square
+    history: [[[i32; 64]; 64]; 2],
+    evaluator: Box<dyn Evaluator>,
+}
 
-            if best_move_value < beta {
-                beta = best_move_value
+impl ThreadState {
+    fn new(evaluator: Box<dyn Evaluator>) -> Box<Self> {
+        Box::new(ThreadState {
+            killers: [[None; 2]; MAX_PLY as usize + 1],
+            history: [[[0; 64]; 64]; 2],
+            evaluator,
+        })
+    }
+
+    /// Killers are tied to plies of the last search so they're dropped, history is only aged
+    fn prepare_for_search(&mut self) {
+        self.killers = [[None; 2]; MAX_PLY as usize + 1];
+        for entry in self.history.iter_mut().flatten().flatten() {
+            *entry /= 2;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.killers = [[None; 2]; MAX_PLY as usize + 1];
+        self.history = [[[0; 64]; 64]; 2];
+        self.evaluator.clear();
+    }
+}
+
+/// Per-thread search state
+struct Searcher<'a> {
+    tt: &'a TranspositionTable,
+    control: &'a SearchControl,
+    options: SearchOptions,
+    state: &'a mut ThreadState,
+    tablebase: Option<&'a dyn Tablebase>,
+    /// Keys of the game history followed by the positions on the path from the root to the current node
+    path: Vec<u64>,
+    /// Depth of the current iteration, which is also the most a line may be extended by
+    root_depth: i32,
+    /// Plies the line to each ply has been extended by so far
+    extended: [i32; MAX_PLY as usize + 1],
+    /// Square the move into each ply captured on and the value of what it took there, a capture there is a
+    /// recapture
+    captured_on: [Option<(Coord, i32)>; MAX_PLY as usize + 1],
+    /// Move left out at each ply while checking whether the hash move is singular
+    excluded: [Option<Move>; MAX_PLY as usize + 1],
+    /// Nodes not yet added to the shared count
+    nodes: u64,
+    /// Once set every node returns immediately and scores are meaningless
+    stopped: bool,
+}
+
+impl<'a> Searcher<'a> {
+    fn new(
+        tt: &'a TranspositionTable,
+        control: &'a SearchControl,
+        options: SearchOptions,
+        state: &'a mut ThreadState,
+        tablebase: Option<&'a dyn Tablebase>,
+        history: &[u64],
+    ) -> Self {
+        state.prepare_for_search();
+        let mut path = Vec::with_capacity(history.len() + MAX_PLY as usize + 1);
+        path.extend_from_slice(history);
+        Searcher {
+            tt,
+            control,
+            options,
+            state,
+            tablebase,
+            path,
+            root_depth: 0,
+            extended: [0; MAX_PLY as usize + 1],
+            captured_on: [None; MAX_PLY as usize + 1],
+            excluded: [None; MAX_PLY as usize + 1],
+            nodes: 0,
+            stopped: false,
+        }
+    }
+
+    #[inline]
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.nodes >= CHECK_INTERVAL {
+            self.flush_nodes();
+        }
+    }
+
+    fn flush_nodes(&mut self) {
+        if self.control.check(self.nodes) {
+            self.stopped = true;
+        }
+        self.nodes = 0;
+    }
+
+    /// Orders moves as: hash move, captures by most valuable victim / least valuable attacker,
+    /// killer moves, then the remaining quiet moves by history score
+    fn order_moves(&self, board: &Board, moves: &mut [Move], tt_move: Option<Move>, ply: i32) {
+        let killers = self.state.killers[ply as usize];
+        let color = board.turn as usize;
+        moves.sort_by_cached_key(|&m| {
+            let score = if Some(m) == tt_move {
+                1_000_000
+            } else if is_capture(board, m) || is_promotion(board, m) {
+                let victim = board.piece_at(m.to).map_or(piece_score(PieceKind::Pawn), |p| piece_score(p.kind));
+                let attacker = board.piece_at(m.from).map_or(0, |p| piece_score(p.kind));
+                100_000 + victim * 10 - attacker / 10
+            } else if Some(m) == killers[0] {
+                90_000
+            } else if Some(m) == killers[1] {
+                89_000
+            } else {
+                self.state.history[color][square_index(m.from)][square_index(m.to)]
+            };
+            std::cmp::Reverse(score)
+        });
+    }
+
+    fn record_cutoff(&mut self, board: &Board, mv: Move, depth: i32, ply: i32) {
+        let killers = &mut self.state.killers[ply as usize];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+        let entry = &mut self.state.history[board.turn as usize][square_index(mv.from)][square_index(mv.to)];
+        *entry = (*entry + depth * depth).min(80_000);
+    }
+
+    /// Thread 0 is the main thread: it owns the result and decides when the search is over.
+    /// Helpers start on alternating depths so they don't all search the same thing at the same time.
+    fn iterative_deepening(&mut self, board: &Board, limits: &SearchLimits, thread_id: usize) -> Option<SearchResult> {
+        let mut root_moves = board.list_all_valid_moves();
+        if root_moves.is_empty() {
+            return None;
+        }
+        let tablebase_score = self.rank_root_moves(board, &mut root_moves);
+        self.path.push(board.zobrist_key());
+        let main_thread = thread_id == 0;
+        let max_depth = if main_thread { limits.max_depth() } else { MAX_PLY as u8 - 1 };
+
+        let wanted_lines = self.options.multi_pv.clamp(1, root_moves.len());
+
+        // Reported as is when the search is stopped before the first iteration finishes
+        let first_score = self.estimate_move(board, root_moves[0]);
+        let mut best = SearchResult {
+            best_move: root_moves[0],
+            score: first_score,
+            depth: 0,
+            nodes: 0,
+            lines: Vec::new(),
+        };
+        let mut lines = vec![(root_moves[0], first_score)];
+        let mut depth = 1 + (thread_id % 2) as u8;
+        // Stops are otherwise only seen every CHECK_INTERVAL nodes
+        self.stopped = self.control.stopped();
+        while depth <= max_depth && !self.stopped {
+            let mut found = self.aspiration_search(board, &mut root_moves, depth, wanted_lines, best.score);
+            if let Some(&(best_move, score)) = found.first() {
+                // When stopped part way only the moves that finished count, but the previous best is
+                // searched first so anything that beat it is still an improvement. The other lines
+                // are kept from the last completed iteration.
+                best.best_move = best_move;
+                best.score = score;
+                if self.stopped {
+                    found.extend(lines.iter().filter(|&&(mv, _)| mv != best_move));
+                    found.truncate(wanted_lines);
+                } else {
+                    best.depth = depth;
+                }
+                lines = found;
+            }
+            if self.stopped {
+                break;
             }
+            if main_thread {
+                if let (Some(mate), Score::Mate(n)) = (limits.mate, best.score()) {
+                    if n > 0 && n as u32 <= mate {
+                        break;
+                    }
+                }
+                if self.control.past_soft_deadline() {
+                    break;
+                }
+            }
+            depth += 1;
+        }
+        self.flush_nodes();
+        best.nodes = self.control.nodes.load(AtomicOrdering::Relaxed);
+        // The search rarely sees far enough to find the mate the tables promise
+        if let Some(score) = tablebase_score.filter(|_| !is_mate_score(best.score)) {
+            best.score = score;
+            lines[0].1 = score;
+        }
+        best.lines = lines
+            .into_iter()
+            .map(|(mv, score)| PrincipalVariation { moves: self.principal_variation(board, mv, best.depth), score })
+            .collect();
+        Some(best)
+    }
 
-            if beta <= alpha {
-                return best_move_value;
+    /// Score of `mv` without searching it: the hash table's score of the position after it when there is
+    /// one, otherwise that position's static evaluation
+    fn estimate_move(&mut self, board: &Board, mv: Move) -> i32 {
+        let mut child = *board;
+        child.do_move_from_coord(mv).unwrap();
+        match self.tt.probe(child.zobrist_key()) {
+            Some(entry) => -score_from_tt(entry.score, 1),
+            None => -self.state.evaluator.evaluate(&child),
+        }
+    }
+
+    /// `first_move` followed by the hash moves, up to `depth` moves in all or until the line repeats
+    fn principal_variation(&self, board: &Board, first_move: Move, depth: u8) -> Vec<Move> {
+        let mut moves = vec![first_move];
+        let mut position = *board;
+        position.do_move_from_coord(first_move).unwrap();
+        let mut seen = vec![board.zobrist_key(), position.zobrist_key()];
+        while moves.len() < depth as usize {
+            let Some(mv) = self.tt.probe(position.zobrist_key()).and_then(|entry| entry.best_move) else {
+                break;
+            };
+            // The entry may belong to another position with the same index bits
+            if position.do_move_from_coord(mv).is_err() {
+                break;
+            }
+            moves.push(mv);
+            if seen.contains(&position.zobrist_key()) {
+                break;
             }
+            seen.push(position.zobrist_key());
         }
+        moves
     }
 
-    best_move_value
-}
+    /// Searches the root with a window around `previous`, the score of the last iteration, widening it on the
+    /// side the score fell out of until it lands inside. Returns what [`Searcher::search_root`] does, except
+    /// that a search stopped part way only counts if it found something better than the window's lower end.
+    fn aspiration_search(&mut self, board: &Board, root_moves: &mut [Move], depth: u8, lines: usize, previous: i32) -> Vec<(Move, i32)> {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = if self.options.aspiration_windows && depth >= ASPIRATION_MIN_DEPTH && !is_mate_score(previous) {
+            (previous - delta, previous + delta)
+        } else {
+            (-INFINITY, INFINITY)
+        };
+        loop {
+            let found = self.search_root(board, root_moves, depth as i32, lines, alpha, beta);
+            let Some(&(_, score)) = found.first() else {
+                return found;
+            };
+            if self.stopped {
+                return if score > alpha { found } else { Vec::new() };
+            }
+            if score <= alpha {
+                alpha = (score - delta).max(-INFINITY);
+            } else if score >= beta {
+                beta = (score + delta).min(INFINITY);
+            } else {
+                return found;
+            }
+            delta *= 2;
+        }
+    }
 
-#[inline]
-fn eval_position(board: &Board, color: PieceColor) -> i32 {
-    match color {
-        PieceColor::White => {
-            if board.in_checkmate.1 {
-                return i32::MAX
+    /// The tablebase, if it covers positions with this many pieces and the options allow probing them
+    fn tablebase_for(&self, board: &Board) -> Option<&'a dyn Tablebase> {
+        let tablebase = self.tablebase?;
+        let pieces = syzygy::piece_count(board);
+        (pieces <= self.options.syzygy_probe_limit.min(tablebase.max_pieces())).then_some(tablebase)
+    }
+
+    /// Keeps only the root moves with the best DTZ outcome, so a won position is converted within the
+    /// fifty-move rule and a lost one is dragged out. Among winning moves only those closest to the next
+    /// capture or pawn move stay, which guarantees progress. Returns the tablebase score of the root.
+    fn rank_root_moves(&self, board: &Board, root_moves: &mut Vec<Move>) -> Option<i32> {
+        let tablebase = self.tablebase_for(board)?;
+        let halfmove_clock = board.halfmove_clock as i32;
+        let mut ranks = Vec::with_capacity(root_moves.len());
+        for &mv in root_moves.iter() {
+            let mut child = *board;
+            child.do_move_from_coord(mv).unwrap();
+            let dtz = if child.halfmove_clock == 0 {
+                match -tablebase.probe_wdl(&child)? {
+                    Wdl::Win => 1,
+                    Wdl::CursedWin => 101,
+                    Wdl::Draw => 0,
+                    Wdl::BlessedLoss => -101,
+                    Wdl::Loss => -1,
+                }
+            } else {
+                let dtz = -tablebase.probe_dtz(&child)?;
+                // Mating also ends the game, as if zeroing the counter
+                if child.get_checkmate() { 1 } else { dtz + dtz.signum() }
+            };
+            // Real wins rank 900 and up and real losses -900 and below, results the fifty-move rule
+            // turns into draws keep their sign for tie-breaking but stay close to zero
+            let rank = match dtz {
+                0 => 0,
+                dtz if dtz > 0 && dtz + halfmove_clock <= 100 => 1000 - dtz,
+                dtz if dtz > 0 => (900 - dtz - halfmove_clock).max(1),
+                dtz if -dtz + halfmove_clock <= 100 => -1000 - dtz,
+                dtz => (-900 - dtz + halfmove_clock).min(-1),
+            };
+            ranks.push(rank);
+        }
+
+        let best = *ranks.iter().max()?;
+        let mut ranks = ranks.into_iter();
+        root_moves.retain(|_| ranks.next() == Some(best));
+        Some(match best {
+            900.. => TB_WIN,
+            ..=-900 => -TB_WIN,
+            rank => rank.signum(),
+        })
+    }
+
+    /// Finds the best `lines` root moves one at a time, each search leaving out the moves already found,
+    /// and moves them to the front in order of score for the next iteration. Returns the moves with their
+    /// scores. When stopped only the first line can come back incomplete, it holds the best of the moves
+    /// that finished and is missing if none did. The first line is searched with the window `alpha..beta`
+    /// and returned alone if its score falls outside, the others always get the full window.
+    fn search_root(&mut self, board: &Board, root_moves: &mut [Move], depth: i32, lines: usize, alpha: i32, beta: i32) -> Vec<(Move, i32)> {
+        let mut found = Vec::with_capacity(lines);
+        let window = (alpha, beta);
+        self.root_depth = depth;
+        for pv_index in 0..lines {
+            let (mut alpha, beta) = if pv_index == 0 { window } else { (-INFINITY, INFINITY) };
+            let mut best: Option<(usize, i32)> = None;
+            for (i, &m) in root_moves.iter().enumerate().skip(pv_index) {
+                let mut child = *board;
+                child.do_move_from_coord(m).unwrap();
+                let depth = depth + self.extend(board, &child, m, 0, false);
+                self.state.evaluator.push(board, &child);
+                let mut score;
+                if i == pv_index || !self.options.principal_variation_search {
+                    score = -self.negamax(&child, depth - 1, 1, -beta, -alpha, true);
+                } else {
+                    score = -self.negamax(&child, depth - 1, 1, -alpha - 1, -alpha, true);
+                    if score > alpha && score < beta {
+                        score = -self.negamax(&child, depth - 1, 1, -beta, -alpha, true);
+                    }
+                }
+                self.state.evaluator.pop();
+                if self.stopped {
+                    break;
+                }
+                if best.is_none_or(|(_, best)| score > best) {
+                    best = Some((i, score));
+                }
+                alpha = alpha.max(score);
+                if alpha >= beta {
+                    break;
+                }
             }
-            if board.in_stalemate.1 {
-                return 0
+
+            let Some((index, score)) = best.filter(|_| pv_index == 0 || !self.stopped) else {
+                break;
+            };
+            root_moves[pv_index..=index].rotate_right(1);
+            found.push((root_moves[pv_index], score));
+            if self.stopped || score <= window.0 || score >= window.1 {
+                break;
             }
-        },
-        PieceColor::Black => {
-            if board.in_checkmate.0 {
-                return i32::MAX
+        }
+
+        let exact = found.first().is_some_and(|&(_, score)| score > window.0 && score < window.1);
+        if !self.stopped && exact {
+            // Later lines can score above earlier ones when the search is unstable
+            found.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+            for (slot, &(mv, _)) in root_moves.iter_mut().zip(&found) {
+                *slot = mv;
             }
-            if board.in_stalemate.0 {
-                return 0
+            if let Some(&(best_move, score)) = found.first() {
+                self.tt.store(board.zobrist_key(), TTEntry { best_move: Some(best_move), score, depth: depth as u8, bound: Bound::Exact });
             }
         }
-    };
-    let mut score = 0;
-    for y in 0..8 {
-        for x in 0..8 {
-            let coord = Coord { x, y };
-            if let Some(piece) = board.piece_at(coord) {
-                let weighted_value = get_weighted_value(piece, coord);
-                score += match piece.color == color {
-                    true => weighted_value,
-                    _ =>  -weighted_value,
+        found
+    }
+
+    /// Extra plies to search `mv` from `board` at `ply` with: one for a check, a pawn push to the seventh rank,
+    /// a recapture after a sacrifice or a `singular` move, as long as the line stays within its budget. Also
+    /// records what the node after the move needs to know.
+    fn extend(&mut self, board: &Board, child: &Board, mv: Move, ply: i32, singular: bool) -> i32 {
+        let ply = ply as usize;
+        let capture = is_capture(board, mv);
+        // En passant captures a pawn on an empty square
+        let taken = board.piece_at(mv.to).map_or(piece_score(PieceKind::Pawn), |piece| piece_score(piece.kind));
+        let extension = if self.options.extensions && self.extended[ply] < self.root_depth {
+            let seventh_rank = match board.piece_at(mv.from) {
+                Some(Piece { kind: PieceKind::Pawn, color: PieceColor::White }) => mv.to.y == 1,
+                Some(Piece { kind: PieceKind::Pawn, color: PieceColor::Black }) => mv.to.y == 6,
+                _ => false,
+            };
+            // Only when the piece taken back was worth more than what it took, even trades are too common
+            let recapture = capture && matches!(self.captured_on[ply], Some((square, gained)) if square == mv.to && taken > gained);
+            (singular || child.get_check() || seventh_rank || recapture) as i32
+        } else {
+            0
+        };
+        self.extended[ply + 1] = self.extended[ply] + extension;
+        self.captured_on[ply + 1] = if capture { Some((mv.to, taken)) } else { None };
+        extension
+    }
+
+    /// Score of a draw at `ply`, from the point of view of the side to move there
+    fn draw_score(&self, ply: i32) -> i32 {
+        if ply % 2 == 0 { DRAW - self.options.contempt } else { DRAW + self.options.contempt }
+    }
+
+    /// Whether `board` repeats a position of the game or of the path to it, or the fifty-move rule ended the game.
+    /// A single repetition is enough, anything that repeats once can be repeated again.
+    fn is_draw(&self, board: &Board, key: u64) -> bool {
+        if board.halfmove_clock >= 100 {
+            return !board.get_checkmate();
+        }
+        // Only positions since the last irreversible move with the same side to move can repeat
+        self.path.iter().rev().take(board.halfmove_clock as usize).skip(1).step_by(2).any(|&earlier| earlier == key)
+    }
+
+    /// Keeps track of the path for repetitions around [`Searcher::search_node`]
+    fn negamax(&mut self, board: &Board, depth: i32, ply: i32, alpha: i32, beta: i32, allow_null: bool) -> i32 {
+        let key = board.zobrist_key();
+        if self.is_draw(board, key) {
+            return self.draw_score(ply);
+        }
+        self.path.push(key);
+        let score = self.search_node(board, depth, ply, alpha, beta, allow_null);
+        self.path.pop();
+        score
+    }
+
+    fn search_node(&mut self, board: &Board, depth: i32, ply: i32, mut alpha: i32, beta: i32, allow_null: bool) -> i32 {
+        if board.get_checkmate() {
+            return mated_in(ply);
+        }
+        if board.get_stalemate() {
+            return self.draw_score(ply);
+        }
+        if depth <= 0 || ply >= MAX_PLY {
+            return self.quiesce(board, ply, alpha, beta);
+        }
+        self.count_node();
+        if self.stopped {
+            return 0;
+        }
+
+        // PV nodes are searched with an open window and decide the line, they're never cut short by the
+        // table or the selective pruning
+        let pv_node = beta - alpha > 1;
+        let key = board.zobrist_key();
+        // Searching without a move finds a different score for the same position, so it stays out of the table
+        let excluded = self.excluded[ply as usize];
+        let tt_entry = if excluded.is_none() { self.tt.probe(key) } else { None };
+        let mut tt_move = None;
+        if let Some(entry) = tt_entry {
+            tt_move = entry.best_move;
+            if !pv_node && entry.depth as i32 >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        // Only right after a capture or pawn move, as the tables ignore the fifty-move counter
+        if excluded.is_none() && board.halfmove_clock == 0 && !syzygy::has_castling_rights(board) {
+            if let Some(wdl) = self.tablebase_for(board).and_then(|tablebase| tablebase.probe_wdl(board)) {
+                let score = match wdl {
+                    Wdl::Win => TB_WIN - ply,
+                    Wdl::Loss => -TB_WIN + ply,
+                    wdl => DRAW + wdl.signum(),
                 };
+                self.tt.store(key, TTEntry { best_move: None, score: score_to_tt(score, ply), depth: (depth + 6).min(MAX_PLY - 1) as u8, bound: Bound::Exact });
+                return score;
+            }
+        }
+
+        let in_check = board.get_check();
+        let static_eval = self.state.evaluator.evaluate(board);
+
+        if self.options.razoring && !pv_node && !in_check && depth <= 2 && static_eval + RAZOR_MARGIN * depth < alpha {
+            let score = self.quiesce(board, ply, alpha, beta);
+            if score < alpha {
+                return score;
+            }
+        }
+
+        if self.options.null_move && allow_null && excluded.is_none() && !pv_node && !in_check && depth >= 3 && static_eval >= beta && !is_mate_score(beta) {
+            let reduction = 2 + depth / 4;
+            let mut null_board = *board;
+            null_board.make_null_move();
+            // Repetitions across a null move aren't real
+            null_board.halfmove_clock = 0;
+            self.extended[ply as usize + 1] = self.extended[ply as usize];
+            self.captured_on[ply as usize + 1] = None;
+            self.state.evaluator.push(board, &null_board);
+            let score = -self.negamax(&null_board, depth - 1 - reduction, ply + 1, -beta, -beta + 1, false);
+            self.state.evaluator.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                // A null move can't prove a mate, so don't return one
+                let score = if is_mate_score(score) { beta } else { score };
+                if non_pawn_material(board, board.turn) > NULL_MOVE_VERIFY_MATERIAL {
+                    return score;
+                }
+                // With little material passing might be the only thing that doesn't lose (zugzwang),
+                // so confirm the cutoff with a real reduced search
+                if self.search_node(board, depth - 1 - reduction, ply, beta - 1, beta, false) >= beta {
+                    return score;
+                }
             }
         }
+
+        let futile = self.options.futility_pruning
+            && !pv_node
+            && !in_check
+            && depth <= 2
+            && !is_mate_score(alpha)
+            && static_eval + FUTILITY_MARGIN * depth <= alpha;
+
+        let mut legal_moves = board.list_all_valid_moves();
+        if legal_moves.is_empty() {
+            return if in_check { mated_in(ply) } else { self.draw_score(ply) };
+        }
+        self.order_moves(board, &mut legal_moves, tt_move, ply);
+
+        // The hash move is singular when every other move falls well short of its score in a shallower search
+        // without it, and deserves a deeper look since everything hangs on it
+        let singular_move = tt_entry
+            .filter(|entry| {
+                self.options.extensions
+                    && depth >= SINGULAR_MIN_DEPTH
+                    && entry.best_move.is_some()
+                    && entry.bound != Bound::Upper
+                    && entry.depth as i32 >= depth - 3
+                    && !is_mate_score(entry.score)
+            })
+            .and_then(|entry| {
+                let singular_beta = score_from_tt(entry.score, ply) - SINGULAR_MARGIN * depth;
+                self.excluded[ply as usize] = entry.best_move;
+                let score = self.search_node(board, depth / 2, ply, singular_beta - 1, singular_beta, false);
+                self.excluded[ply as usize] = None;
+                (score < singular_beta).then_some(entry.best_move).flatten()
+            });
+        if self.stopped {
+            return 0;
+        }
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for (i, m) in legal_moves.into_iter().filter(|&m| Some(m) != excluded).enumerate() {
+            let quiet = !is_capture(board, m) && !is_promotion(board, m);
+            let mut child = *board;
+            child.do_move_from_coord(m).unwrap();
+            let gives_check = child.get_check();
+
+            if futile && i > 0 && quiet && !gives_check {
+                best_score = best_score.max(static_eval + FUTILITY_MARGIN * depth);
+                continue;
+            }
+
+            let reduce = self.options.late_move_reductions
+                && depth >= 3
+                && i >= 3
+                && quiet
+                && !in_check
+                && !gives_check;
+            let reduction = if reduce { late_move_reduction(depth, i) } else { 0 };
+            let new_depth = depth - 1 + self.extend(board, &child, m, ply, singular_move == Some(m));
+            let pvs = self.options.principal_variation_search;
+            self.state.evaluator.push(board, &child);
+            let mut score;
+            if i == 0 || (reduction == 0 && !pvs) {
+                score = -self.negamax(&child, new_depth, ply + 1, -beta, -alpha, true);
+            } else {
+                // Later moves only have to show they're no better than alpha, which a null window does cheaply
+                score = -self.negamax(&child, new_depth - reduction, ply + 1, -alpha - 1, -alpha, true);
+                if score > alpha && reduction > 0 && pvs {
+                    score = -self.negamax(&child, new_depth, ply + 1, -alpha - 1, -alpha, true);
+                }
+                if score > alpha && (score < beta || !pvs) {
+                    score = -self.negamax(&child, new_depth, ply + 1, -beta, -alpha, true);
+                }
+            }
+            self.state.evaluator.pop();
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+                if score > alpha {
+                    alpha = score;
+                }
+                if alpha >= beta {
+                    if quiet {
+                        self.record_cutoff(board, m, depth, ply);
+                    }
+                    break;
+                }
+            }
+        }
+
+        if excluded.is_some() {
+            return best_score;
+        }
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha && best_move.is_some() {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(key, TTEntry { best_move, score: score_to_tt(best_score, ply), depth: depth as u8, bound });
+        best_score
+    }
+
+    /// Searches captures and promotions only until the position is quiet
+    fn quiesce(&mut self, board: &Board, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        if board.get_checkmate() {
+            return mated_in(ply);
+        }
+        if board.get_stalemate() {
+            return self.draw_score(ply);
+        }
+        self.count_node();
+        if self.stopped {
+            return 0;
+        }
+
+        let stand_pat = self.state.evaluator.evaluate(board);
+        if !self.options.quiescence || ply >= MAX_PLY || stand_pat >= beta {
+            return stand_pat;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+
+        let mut captures: Vec<Move> = board
+            .list_all_valid_moves()
+            .into_iter()
+            .filter(|&m| is_capture(board, m) || is_promotion(board, m))
+            .collect();
+        self.order_moves(board, &mut captures, None, ply);
+
+        let mut best_score = stand_pat;
+        for m in captures {
+            let mut child = *board;
+            child.do_move_from_coord(m).unwrap();
+            self.state.evaluator.push(board, &child);
+            let score = -self.quiesce(&child, ply + 1, -beta, -alpha);
+            self.state.evaluator.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                }
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        best_score
     }
-    score
-}
\ No newline at end of file
+}
+
+#[inline]
+fn square_index(coord: Coord) -> usize {
+    coord.y * 8 + coord.x
+}
@@ -0,0 +1,227 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::pieces::{
+    Piece,
+    PieceColor,
+    PieceKind,
+};
+use crate::pawns::{
+    PawnHashTable,
+    PawnStructure,
+};
+use crate::activity::piece_activity;
+use crate::endgame::evaluate_endgame;
+use crate::params::{
+    EvalParams,
+    DEFAULT_PARAMS,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::sync::Arc;
+
+/// A pair of middlegame and endgame values, blended by the game phase at the end of the evaluation
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "[i32; 2]", into = "[i32; 2]")]
+pub struct Tapered {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Tapered {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Tapered { mg, eg }
+    }
+
+    /// Interpolates between the endgame value (phase 0) and the middlegame value (phase [`PHASE_MAX`])
+    pub fn blend(self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (PHASE_MAX - phase)) / PHASE_MAX
+    }
+}
+
+impl From<[i32; 2]> for Tapered {
+    fn from([mg, eg]: [i32; 2]) -> Self {
+        Tapered::new(mg, eg)
+    }
+}
+
+impl From<Tapered> for [i32; 2] {
+    fn from(value: Tapered) -> Self {
+        [value.mg, value.eg]
+    }
+}
+
+impl Add for Tapered {
+    type Output = Tapered;
+    fn add(self, other: Tapered) -> Tapered {
+        Tapered::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl AddAssign for Tapered {
+    fn add_assign(&mut self, other: Tapered) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Tapered {
+    type Output = Tapered;
+    fn sub(self, other: Tapered) -> Tapered {
+        Tapered::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl SubAssign for Tapered {
+    fn sub_assign(&mut self, other: Tapered) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Tapered {
+    type Output = Tapered;
+    fn neg(self) -> Tapered {
+        Tapered::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Tapered {
+    type Output = Tapered;
+    fn mul(self, factor: i32) -> Tapered {
+        Tapered::new(self.mg * factor, self.eg * factor)
+    }
+}
+
+/// Phase with all the minor and major pieces still on the board
+pub const PHASE_MAX: i32 = 24;
+
+fn phase_weight(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Knight | PieceKind::Bishop => 1,
+        PieceKind::Rook => 2,
+        PieceKind::Queen => 4,
+        PieceKind::Pawn | PieceKind::King => 0,
+    }
+}
+
+/// Game phase from the remaining material, from 0 (pawn endgame) to [`PHASE_MAX`] (opening).
+/// Promotions can push the raw count above the maximum so it is capped.
+pub fn game_phase(board: &Board) -> i32 {
+    let mut phase = 0;
+    for row in board.board.iter() {
+        for square in row.iter() {
+            if let Square::Occupied(piece) = square {
+                phase += phase_weight(piece.kind);
+            }
+        }
+    }
+    phase.min(PHASE_MAX)
+}
+
+/// Material value of `kind` with the built-in parameters
+pub fn piece_value(kind: PieceKind) -> Tapered {
+    DEFAULT_PARAMS.piece_value(kind)
+}
+
+/// Material plus piece-square value of `piece` standing on `position` with the built-in parameters
+pub fn piece_square_value(piece: Piece, position: Coord) -> Tapered {
+    DEFAULT_PARAMS.piece_square_value(piece, position)
+}
+
+/// A static evaluation function the search can be run with. Each search thread works on its own
+/// instance, so implementations are free to keep caches or incremental state in `self`.
+pub trait Evaluator: Send {
+    /// Score in centipawns from the point of view of the side to move. Must stay well inside
+    /// the tablebase and mate bounds of the search, see [`crate::engine::TB_BOUND`].
+    fn evaluate(&mut self, board: &Board) -> i32;
+
+    /// A new evaluator with the same parameters, for another search thread. Caches don't need to be copied.
+    fn fork(&self) -> Box<dyn Evaluator>;
+
+    /// Forgets anything remembered from earlier positions, called when a new game starts
+    fn clear(&mut self) {}
+
+    /// Called when the search steps from `parent` to `child`, by a move or a null move. Evaluators with
+    /// incremental state update it here, and undo the step when the matching [`Evaluator::pop`] comes.
+    fn push(&mut self, _parent: &Board, _child: &Board) {}
+
+    /// Called when the search returns to the parent of the last pushed position
+    fn pop(&mut self) {}
+}
+
+/// The built-in hand-written evaluation: material, piece-square tables, pawn structure and piece activity
+pub struct PstEvaluator {
+    params: Arc<EvalParams>,
+    pawns: PawnHashTable,
+}
+
+impl Default for PstEvaluator {
+    fn default() -> Self {
+        PstEvaluator::new()
+    }
+}
+
+impl PstEvaluator {
+    pub fn new() -> Self {
+        PstEvaluator::with_params(DEFAULT_PARAMS.clone())
+    }
+
+    pub fn with_params(params: EvalParams) -> Self {
+        PstEvaluator { params: Arc::new(params), pawns: PawnHashTable::new() }
+    }
+
+    pub fn params(&self) -> &EvalParams {
+        &self.params
+    }
+}
+
+impl Evaluator for PstEvaluator {
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        evaluate_cached(board, &self.params, &mut self.pawns)
+    }
+
+    fn fork(&self) -> Box<dyn Evaluator> {
+        Box::new(PstEvaluator { params: Arc::clone(&self.params), pawns: PawnHashTable::new() })
+    }
+
+    fn clear(&mut self) {
+        self.pawns.clear();
+    }
+}
+
+/// Static evaluation with the built-in parameters, from the point of view of the side to move
+pub fn evaluate(board: &Board) -> i32 {
+    evaluate_with(board, &DEFAULT_PARAMS)
+}
+
+/// Static evaluation with `params`, from the point of view of the side to move
+pub fn evaluate_with(board: &Board, params: &EvalParams) -> i32 {
+    evaluate_with_pawns(board, params, &PawnStructure::analyze(board, &params.pawns))
+}
+
+/// Same as [`evaluate_with`] but looks the pawn structure up in `pawns` first. The table must
+/// only ever be used with the same `params`.
+pub fn evaluate_cached(board: &Board, params: &EvalParams, pawns: &mut PawnHashTable) -> i32 {
+    evaluate_with_pawns(board, params, &pawns.probe(board, &params.pawns))
+}
+
+fn evaluate_with_pawns(board: &Board, params: &EvalParams, pawns: &PawnStructure) -> i32 {
+    let mut score = pawns.score + pawns.passed_pawn_path_bonus(board, &params.pawns) + piece_activity(board, &params.activity);
+    for y in 0..8 {
+        for x in 0..8 {
+            if let Square::Occupied(piece) = board.board[y][x] {
+                let value = params.piece_square_value(piece, Coord { x, y });
+                match piece.color {
+                    PieceColor::White => score += value,
+                    PieceColor::Black => score -= value,
+                }
+            }
+        }
+    }
+    let score = evaluate_endgame(board, score.blend(game_phase(board)));
+    match board.turn {
+        PieceColor::White => score,
+        PieceColor::Black => -score,
+    }
+}
@@ -16,11 +16,14 @@ use crate::pieces::{
     PieceKind
 };
 use crate::engine::{
-    make_best_move
+    Engine,
+    SearchLimits,
 };
+use crate::skill::Skill;
 
 
-pub fn run(get_cpu_move: fn(&Board) -> Move, starting_board: Board) -> iced::Result {
+/// Plays against `get_cpu_move`, which gets the starting board and the moves played since
+pub fn run(get_cpu_move: fn(&Board, &[Move]) -> Move, starting_board: Board) -> iced::Result {
     {
         let mut x = GET_CPU_MOVE.lock().unwrap();
         *x = get_cpu_move;
@@ -42,8 +45,9 @@ pub fn run(get_cpu_move: fn(&Board) -> Move, starting_board: Board) -> iced::Res
 }
 
 lazy_static! {
-    static ref GET_CPU_MOVE: Mutex<fn(&Board) -> Move> = Mutex::new(best_move);
+    static ref GET_CPU_MOVE: Mutex<fn(&Board, &[Move]) -> Move> = Mutex::new(best_move);
     static ref STARTING_BOARD: Mutex<Board> = Mutex::new(Board::default());
+    static ref ENGINE: Mutex<Engine> = Mutex::new(Engine::new());
 }
 
 const SQUARE_SIZE: u16 = 48;
@@ -70,11 +74,23 @@ pub fn get_symbol(piece: &Piece) -> impl ToString {
 	}
 }
 
-pub fn best_move(board: &Board) -> Move {
-    make_best_move(AI_DEPTH, board).unwrap()
+/// Makes [`best_move`] play at `skill` instead of full strength
+pub fn set_skill(skill: Skill) {
+    ENGINE.lock().unwrap().set_skill(skill).unwrap();
 }
 
-pub fn random_move(board: &Board) -> Move {
+/// The engine knows the whole game so it can steer clear of repetitions
+pub fn best_move(start: &Board, moves: &[Move]) -> Move {
+    let mut engine = ENGINE.lock().unwrap();
+    engine.set_position(*start, moves).unwrap();
+    engine.search(SearchLimits::to_depth(AI_DEPTH + 1)).unwrap().unwrap().best_move
+}
+
+pub fn random_move(start: &Board, moves: &[Move]) -> Move {
+    let mut board = *start;
+    for &mv in moves {
+        board.do_move_from_coord(mv).unwrap();
+    }
     let moves = board.list_all_valid_moves();
 
     let mut rng = thread_rng();
@@ -214,8 +230,10 @@ impl container::StyleSheet for ChessBoardStyle {
 
 #[derive(Clone)]
 pub struct ChessBoard {
-    get_cpu_move: fn(&Board) -> Move,
+    get_cpu_move: fn(&Board, &[Move]) -> Move,
     starting_board: Board,
+    /// Played since `starting_board`
+    moves: Vec<Move>,
     result: GameResult,
     from_square: Option<Coord>,
     board: Board,
@@ -232,6 +250,7 @@ impl Default for ChessBoard {
         Self {
             get_cpu_move,
             starting_board,
+            moves: Vec::new(),
             result: GameResult::Continuing,
             from_square: None,
             board,
@@ -260,6 +279,7 @@ impl Sandbox for ChessBoard {
         match self.result {
             GameResult::Victory(_) | GameResult::Stalemate => {
                 self.board = self.starting_board;
+                self.moves.clear();
                 self.result = GameResult::Continuing;
             },
             _ => {
@@ -273,6 +293,7 @@ impl Sandbox for ChessBoard {
                         self.from_square = None;
                         self.result = match self.board.do_move_from_coord(m) {
                             Ok(_) => {
+                                self.moves.push(m);
                                 if self.board.get_checkmate() {
                                     GameResult::Victory(self.board.turn)
                                 } else if self.board.get_stalemate() {
@@ -285,9 +306,10 @@ impl Sandbox for ChessBoard {
                         };
                         match self.result {
                             GameResult::Continuing => {
-                                let cpu_move = (self.get_cpu_move)(&self.board);
+                                let cpu_move = (self.get_cpu_move)(&self.starting_board, &self.moves);
                                 self.result = match self.board.do_move_from_coord(cpu_move) {
                                     Ok(_) => {
+                                        self.moves.push(cpu_move);
                                         if self.board.get_checkmate() {
                                             GameResult::Victory(self.board.turn)
                                         } else if self.board.get_stalemate() {
@@ -301,6 +323,7 @@ impl Sandbox for ChessBoard {
                             },
                             GameResult::Victory(_) | GameResult::Stalemate => {
                                 self.board = self.starting_board;
+                                self.moves.clear();
                             }
                             _ => {}
                         }
@@ -313,7 +336,7 @@ impl Sandbox for ChessBoard {
         }
     }
 
-    fn view(&mut self) -> Element<Message> {
+    fn view(&mut self) -> Element<'_, Message> {
         let mut result = Column::new().spacing(0).align_items(Align::Center);
         let mut row = Row::new().spacing(0).align_items(Align::Center);
         let mut i = 0;
@@ -5,4 +5,18 @@ pub mod pieces;
 pub mod tests;
 pub mod gui;
 pub mod engine;
+pub mod evaluation;
+pub mod pawns;
+pub mod activity;
+pub mod params;
+pub mod tuner;
+pub mod nnue;
+pub mod datagen;
+pub mod book;
+pub mod pgn;
+pub mod syzygy;
+pub mod retrograde;
+pub mod endgame;
+pub mod skill;
+pub mod solver;
 
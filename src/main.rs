@@ -2,7 +2,8 @@
 use chess_engine::{
     board::Board,
     // pieces::PieceColor,
-    gui::{run, best_move},
+    gui::{run, best_move, set_skill},
+    skill::Skill,
 };
 
 
@@ -14,6 +15,10 @@ use chess_engine::{
 // }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Optional skill level, from 0 up to full strength at 20
+    if let Some(level) = std::env::args().nth(1) {
+        set_skill(Skill::new(level.parse()?));
+    }
     let b = Board::default();
     run(best_move, b)?;
     Ok(())
@@ -0,0 +1,334 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::evaluation::Evaluator;
+use crate::pieces::{
+    Piece,
+    PieceColor,
+    PieceKind,
+};
+use std::{fmt, fs, io, path::Path, sync::Arc};
+
+#[derive(Debug)]
+pub enum NnueError {
+    Io(io::Error),
+    /// The file isn't a network or its sizes don't add up
+    Format(String),
+}
+pub type Result<T> = std::result::Result<T, NnueError>;
+
+impl std::error::Error for NnueError {}
+
+impl fmt::Display for NnueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NnueError::Io(err) => write!(f, "Could not access network file: {err}"),
+            NnueError::Format(desc) => write!(f, "Invalid network file: {desc}"),
+        }
+    }
+}
+
+impl From<io::Error> for NnueError {
+    fn from(err: io::Error) -> Self {
+        NnueError::Io(err)
+    }
+}
+
+const MAGIC: &[u8; 4] = b"NNUE";
+const VERSION: u32 = 1;
+
+/// HalfKP inputs: own king square × non-king piece (5 kinds × 2 colors) × piece square, per perspective
+pub const FEATURES: usize = 64 * 10 * 64;
+/// Hidden activations are clipped to `0..=QA`
+pub const QA: i32 = 255;
+/// Output weights are scaled by `QB`
+pub const QB: i32 = 64;
+/// Converts the network's output to centipawns
+pub const SCALE: i32 = 400;
+/// Keeps scores from a badly trained network away from the mate range
+const MAX_EVAL: i32 = 20_000;
+
+/// A quantized HalfKP network: `FEATURES → hidden` shared by both perspectives, then the two
+/// clipped hidden layers (side to move first) straight to a single output.
+///
+/// File layout, all little endian: `b"NNUE"`, version (u32), hidden size (u32), feature weights
+/// (`FEATURES × hidden` i16, one row of `hidden` per feature), feature biases (`hidden` i16), output
+/// weights (`2 × hidden` i16) and output bias (i32).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+impl Network {
+    pub fn new(
+        hidden: usize,
+        feature_weights: Vec<i16>,
+        feature_biases: Vec<i16>,
+        output_weights: Vec<i16>,
+        output_bias: i32,
+    ) -> Result<Self> {
+        if hidden == 0 {
+            return Err(NnueError::Format("Hidden layer is empty".to_string()));
+        }
+        if feature_weights.len() != FEATURES * hidden || feature_biases.len() != hidden || output_weights.len() != 2 * hidden {
+            return Err(NnueError::Format("Layer sizes don't match the hidden size".to_string()));
+        }
+        Ok(Network { hidden, feature_weights, feature_biases, output_weights, output_bias })
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || NnueError::Format("File is truncated".to_string());
+        let mut rest = bytes;
+        let mut take = |n: usize| -> Result<&[u8]> {
+            if rest.len() < n {
+                return Err(truncated());
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+
+        if take(4)? != MAGIC {
+            return Err(NnueError::Format("Missing NNUE header".to_string()));
+        }
+        let version = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(NnueError::Format(format!("Unsupported version {version}")));
+        }
+        let hidden = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let mut read_i16s = |n: usize| -> Result<Vec<i16>> {
+            let raw = take(n.checked_mul(2).ok_or_else(truncated)?)?;
+            Ok(raw.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect())
+        };
+        let feature_weights = read_i16s(FEATURES.saturating_mul(hidden))?;
+        let feature_biases = read_i16s(hidden)?;
+        let output_weights = read_i16s(2 * hidden)?;
+        let output_bias = i32::from_le_bytes(take(4)?.try_into().unwrap());
+        if !rest.is_empty() {
+            return Err(NnueError::Format("Trailing data after the network".to_string()));
+        }
+        Network::new(hidden, feature_weights, feature_biases, output_weights, output_bias)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + 2 * (self.feature_weights.len() + 3 * self.hidden));
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for value in self.feature_weights.iter().chain(&self.feature_biases).chain(&self.output_weights) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    fn feature_row(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    /// Output in centipawns for the side to move given both perspectives' accumulators
+    fn output(&self, us: &[i16], them: &[i16]) -> i32 {
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+        let layer = |values: &[i16], weights: &[i16]| -> i32 {
+            values
+                .iter()
+                .zip(weights)
+                .map(|(&v, &w)| (v as i32).clamp(0, QA) * w as i32)
+                .sum()
+        };
+        let sum = layer(us, our_weights) + layer(them, their_weights) + self.output_bias;
+        (sum as i64 * SCALE as i64 / (QA * QB) as i64).clamp(-MAX_EVAL as i64, MAX_EVAL as i64) as i32
+    }
+}
+
+/// Square index as seen from `perspective`, which always plays up the board
+#[inline]
+fn oriented(perspective: PieceColor, coord: Coord) -> usize {
+    match perspective {
+        PieceColor::White => coord.y * 8 + coord.x,
+        PieceColor::Black => (7 - coord.y) * 8 + coord.x,
+    }
+}
+
+/// Input index of `piece` on `coord` for `perspective` whose king stands on `king`. Kings aren't inputs.
+#[inline]
+fn feature_index(perspective: PieceColor, king: Coord, piece: Piece, coord: Coord) -> Option<usize> {
+    let kind = match piece.kind {
+        PieceKind::King => return None,
+        kind => kind as usize,
+    };
+    let piece_index = kind * 2 + (piece.color != perspective) as usize;
+    Some((oriented(perspective, king) * 10 + piece_index) * 64 + oriented(perspective, coord))
+}
+
+#[inline]
+fn king_of(board: &Board, color: PieceColor) -> Coord {
+    match color {
+        PieceColor::White => board.white_king,
+        PieceColor::Black => board.black_king,
+    }
+}
+
+/// Hidden layer inputs of both perspectives for one position
+#[derive(Clone)]
+struct Accumulator {
+    /// Placement these values were computed for
+    placement: [[Square; 8]; 8],
+    /// Indexed by `PieceColor as usize`
+    values: [Vec<i16>; 2],
+}
+
+impl Accumulator {
+    fn new(hidden: usize) -> Self {
+        // No real position is empty, so this never matches one before it's been computed
+        Accumulator { placement: Board::empty().board, values: [vec![0; hidden], vec![0; hidden]] }
+    }
+
+    fn refresh(&mut self, network: &Network, board: &Board, perspective: PieceColor) {
+        let values = &mut self.values[perspective as usize];
+        values.copy_from_slice(&network.feature_biases);
+        let king = king_of(board, perspective);
+        for y in 0..8 {
+            for x in 0..8 {
+                let coord = Coord { x, y };
+                if let Some(feature) = board.piece_at(coord).and_then(|piece| feature_index(perspective, king, piece, coord)) {
+                    add_row(values, network.feature_row(feature));
+                }
+            }
+        }
+    }
+}
+
+// Plain element-wise loops over equal-length slices, which the compiler turns into SIMD adds
+#[inline]
+fn add_row(values: &mut [i16], row: &[i16]) {
+    for (v, w) in values.iter_mut().zip(row) {
+        *v = v.wrapping_add(*w);
+    }
+}
+
+#[inline]
+fn sub_row(values: &mut [i16], row: &[i16]) {
+    for (v, w) in values.iter_mut().zip(row) {
+        *v = v.wrapping_sub(*w);
+    }
+}
+
+/// Evaluates with a [`Network`]. The accumulators follow the search move by move, only adding and
+/// removing the inputs of pieces that changed square, and are recomputed in full when a king moves
+/// or a position comes in that doesn't follow from the last one.
+pub struct NnueEvaluator {
+    network: Arc<Network>,
+    /// One accumulator per ply of the current line, `stack[top]` belongs to the latest position
+    stack: Vec<Accumulator>,
+    top: usize,
+}
+
+impl NnueEvaluator {
+    pub fn new(network: Network) -> Self {
+        NnueEvaluator::with_shared(Arc::new(network))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(NnueEvaluator::new(Network::load(path)?))
+    }
+
+    fn with_shared(network: Arc<Network>) -> Self {
+        let hidden = network.hidden;
+        NnueEvaluator { network, stack: vec![Accumulator::new(hidden)], top: 0 }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Makes sure the top accumulator belongs to `board`
+    fn sync(&mut self, board: &Board) {
+        let accumulator = &mut self.stack[self.top];
+        if accumulator.placement != board.board {
+            for perspective in [PieceColor::White, PieceColor::Black] {
+                accumulator.refresh(&self.network, board, perspective);
+            }
+            accumulator.placement = board.board;
+        }
+    }
+}
+
+impl Evaluator for NnueEvaluator {
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        self.sync(board);
+        let values = &self.stack[self.top].values;
+        let us = &values[board.turn as usize];
+        let them = &values[board.turn.opposite() as usize];
+        self.network.output(us, them)
+    }
+
+    fn fork(&self) -> Box<dyn Evaluator> {
+        Box::new(NnueEvaluator::with_shared(Arc::clone(&self.network)))
+    }
+
+    fn clear(&mut self) {
+        self.stack.truncate(1);
+        self.stack[0] = Accumulator::new(self.network.hidden);
+        self.top = 0;
+    }
+
+    fn push(&mut self, parent: &Board, child: &Board) {
+        self.sync(parent);
+        if self.top + 1 == self.stack.len() {
+            self.stack.push(Accumulator::new(self.network.hidden));
+        }
+        let (done, next) = self.stack.split_at_mut(self.top + 1);
+        let (from, to) = (&done[self.top], &mut next[0]);
+        for perspective in [PieceColor::White, PieceColor::Black] {
+            let king = king_of(child, perspective);
+            if king != king_of(parent, perspective) {
+                // Every input depends on the king square
+                to.refresh(&self.network, child, perspective);
+                continue;
+            }
+            let values = &mut to.values[perspective as usize];
+            values.copy_from_slice(&from.values[perspective as usize]);
+            for y in 0..8 {
+                for x in 0..8 {
+                    if parent.board[y][x] == child.board[y][x] {
+                        continue;
+                    }
+                    let coord = Coord { x, y };
+                    if let Some(feature) = parent.piece_at(coord).and_then(|piece| feature_index(perspective, king, piece, coord)) {
+                        sub_row(values, self.network.feature_row(feature));
+                    }
+                    if let Some(feature) = child.piece_at(coord).and_then(|piece| feature_index(perspective, king, piece, coord)) {
+                        add_row(values, self.network.feature_row(feature));
+                    }
+                }
+            }
+        }
+        to.placement = child.board;
+        self.top += 1;
+    }
+
+    fn pop(&mut self) {
+        self.top = self.top.saturating_sub(1);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::board::Coord;
+use crate::evaluation::Tapered;
+use crate::pieces::{
+    Piece,
+    PieceColor,
+    PieceKind,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path};
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Io(io::Error),
+    /// The file isn't valid TOML or doesn't match the parameter layout
+    Parse(String),
+    Invalid(String),
+}
+pub type Result<T> = std::result::Result<T, ParamsError>;
+
+impl std::error::Error for ParamsError {}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamsError::Io(err) => write!(f, "Could not access parameter file: {err}"),
+            ParamsError::Parse(desc) => write!(f, "Could not parse parameters: {desc}"),
+            ParamsError::Invalid(desc) => write!(f, "Invalid parameters: {desc}"),
+        }
+    }
+}
+
+impl From<io::Error> for ParamsError {
+    fn from(err: io::Error) -> Self {
+        ParamsError::Io(err)
+    }
+}
+
+lazy_static! {
+    /// The built-in parameters, shared by everything that doesn't bring its own
+    pub(crate) static ref DEFAULT_PARAMS: EvalParams = EvalParams::default();
+}
+
+/// Every weight of the hand-written evaluation. Tapered values are written as `[middlegame, endgame]`
+/// pairs and piece-square tables are from White's point of view with rank 8 first; Black uses them
+/// mirrored vertically.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvalParams {
+    pub material: Material,
+    pub psqt: PieceSquareTables,
+    pub pawns: PawnParams,
+    pub activity: ActivityParams,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    pub pawn: Tapered,
+    pub knight: Tapered,
+    pub bishop: Tapered,
+    pub rook: Tapered,
+    pub queen: Tapered,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PieceSquareTable {
+    pub mg: [[i32; 8]; 8],
+    pub eg: [[i32; 8]; 8],
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PieceSquareTables {
+    pub pawn: PieceSquareTable,
+    pub knight: PieceSquareTable,
+    pub bishop: PieceSquareTable,
+    pub rook: PieceSquareTable,
+    pub queen: PieceSquareTable,
+    pub king: PieceSquareTable,
+}
+
+/// Pawn structure terms, the per-rank arrays are indexed by the rank relative to the pawn's side (rank 1 first)
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PawnParams {
+    pub doubled: Tapered,
+    pub isolated: Tapered,
+    pub backward: Tapered,
+    /// Pawn defended by another pawn or standing next to one
+    pub connected: [Tapered; 8],
+    pub passed: [Tapered; 8],
+    /// Extra for a passed pawn with nothing standing between it and promotion
+    pub passed_free_path: [Tapered; 8],
+}
+
+/// Piece activity and king safety terms. The per-piece arrays are indexed knight, bishop, rook, queen.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActivityParams {
+    /// Bonus per reachable square
+    pub mobility: [Tapered; 4],
+    /// Reachable squares at which a piece counts as neither active nor passive
+    pub mobility_neutral: [i32; 4],
+    /// Weight of each attacked king-zone square
+    pub king_attack_weight: [i32; 4],
+    /// Middlegame bonus for the attacking side by accumulated king-zone attack weight, the last entry
+    /// is used for anything beyond the end
+    pub king_safety: Vec<i32>,
+    /// Pawn directly in front of the king and one a further square ahead
+    pub pawn_shield: [Tapered; 2],
+    /// File next to the king without a pawn of its own
+    pub king_semi_open_file: Tapered,
+    /// Extra if that file has no pawns at all
+    pub king_open_file: Tapered,
+    pub bishop_pair: Tapered,
+    pub rook_open_file: Tapered,
+    pub rook_semi_open_file: Tapered,
+    pub rook_seventh_rank: Tapered,
+    pub knight_outpost: Tapered,
+}
+
+impl ActivityParams {
+    /// Index into the per-piece arrays, `None` for pawns and kings
+    #[inline]
+    pub fn piece_index(kind: PieceKind) -> Option<usize> {
+        match kind {
+            PieceKind::Knight => Some(0),
+            PieceKind::Bishop => Some(1),
+            PieceKind::Rook => Some(2),
+            PieceKind::Queen => Some(3),
+            PieceKind::Pawn | PieceKind::King => None,
+        }
+    }
+}
+
+impl EvalParams {
+    pub fn piece_value(&self, kind: PieceKind) -> Tapered {
+        match kind {
+            PieceKind::Pawn => self.material.pawn,
+            PieceKind::Knight => self.material.knight,
+            PieceKind::Bishop => self.material.bishop,
+            PieceKind::Rook => self.material.rook,
+            PieceKind::Queen => self.material.queen,
+            PieceKind::King => Tapered::default(),
+        }
+    }
+
+    pub fn piece_square_table(&self, kind: PieceKind) -> &PieceSquareTable {
+        match kind {
+            PieceKind::Pawn => &self.psqt.pawn,
+            PieceKind::Knight => &self.psqt.knight,
+            PieceKind::Bishop => &self.psqt.bishop,
+            PieceKind::Rook => &self.psqt.rook,
+            PieceKind::Queen => &self.psqt.queen,
+            PieceKind::King => &self.psqt.king,
+        }
+    }
+
+    /// Material plus piece-square value of `piece` standing on `position`
+    pub fn piece_square_value(&self, piece: Piece, position: Coord) -> Tapered {
+        let table = self.piece_square_table(piece.kind);
+        let y = match piece.color {
+            PieceColor::White => position.y,
+            PieceColor::Black => 7 - position.y,
+        };
+        Tapered::new(table.mg[y][position.x], table.eg[y][position.x]) + self.piece_value(piece.kind)
+    }
+
+    /// Calls `f` on every tunable weight, always in the same order. Counts and indices
+    /// (`mobility_neutral`, `king_attack_weight`) are left out.
+    fn visit_weights(&mut self, mut f: impl FnMut(&mut i32)) {
+        let mut tapered = |value: &mut Tapered| {
+            f(&mut value.mg);
+            f(&mut value.eg);
+        };
+        let material = &mut self.material;
+        for value in [&mut material.pawn, &mut material.knight, &mut material.bishop, &mut material.rook, &mut material.queen] {
+            tapered(value);
+        }
+
+        let pawns = &mut self.pawns;
+        for value in [&mut pawns.doubled, &mut pawns.isolated, &mut pawns.backward] {
+            tapered(value);
+        }
+        for value in pawns.connected.iter_mut().chain(&mut pawns.passed).chain(&mut pawns.passed_free_path) {
+            tapered(value);
+        }
+
+        let activity = &mut self.activity;
+        for value in activity.mobility.iter_mut().chain(&mut activity.pawn_shield) {
+            tapered(value);
+        }
+        for value in [
+            &mut activity.king_semi_open_file,
+            &mut activity.king_open_file,
+            &mut activity.bishop_pair,
+            &mut activity.rook_open_file,
+            &mut activity.rook_semi_open_file,
+            &mut activity.rook_seventh_rank,
+            &mut activity.knight_outpost,
+        ] {
+            tapered(value);
+        }
+
+        let psqt = &mut self.psqt;
+        for table in [&mut psqt.pawn, &mut psqt.knight, &mut psqt.bishop, &mut psqt.rook, &mut psqt.queen, &mut psqt.king] {
+            table.mg.iter_mut().flatten().chain(table.eg.iter_mut().flatten()).for_each(&mut f);
+        }
+        activity.king_safety.iter_mut().for_each(f);
+    }
+
+    /// Every tunable weight as one flat vector, see [`EvalParams::set_weights`]
+    pub fn weights(&self) -> Vec<i32> {
+        let mut weights = Vec::new();
+        self.clone().visit_weights(|weight| weights.push(*weight));
+        weights
+    }
+
+    /// Overwrites the tunable weights from a vector laid out like the one [`EvalParams::weights`] returns
+    pub fn set_weights(&mut self, weights: &[i32]) {
+        let mut weights = weights.iter();
+        self.visit_weights(|weight| *weight = *weights.next().expect("weight vector too short"));
+        assert!(weights.next().is_none(), "weight vector too long");
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let params: EvalParams = toml::from_str(text).map_err(|err| ParamsError::Parse(err.to_string()))?;
+        if params.activity.king_safety.is_empty() {
+            return Err(ParamsError::Invalid("king_safety needs at least one entry".to_string()));
+        }
+        Ok(params)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let text = toml::to_string(self).expect("evaluation parameters always serialize");
+        // Lay piece-square tables out one rank per line so they read like a board
+        let mut out = String::with_capacity(text.len() * 2);
+        for line in text.lines() {
+            if let Some(rows) = line.strip_prefix("mg = ").or_else(|| line.strip_prefix("eg = ")) {
+                out.push_str(&line[..5]);
+                out.push_str("[\n    ");
+                out.push_str(&rows[1..rows.len() - 1].replace("], [", "],\n    ["));
+                out.push_str(",\n]\n");
+            } else {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        EvalParams::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        let table = |mg, eg| PieceSquareTable { mg, eg };
+        let t = Tapered::new;
+        EvalParams {
+            material: Material {
+                pawn: t(100, 120),
+                knight: t(300, 290),
+                bishop: t(300, 310),
+                rook: t(500, 530),
+                queen: t(900, 940),
+            },
+            psqt: PieceSquareTables {
+                pawn: table(PAWN_MG, PAWN_EG),
+                knight: table(KNIGHT_MG, KNIGHT_EG),
+                bishop: table(BISHOP_MG, BISHOP_EG),
+                rook: table(ROOK_MG, ROOK_EG),
+                queen: table(QUEEN_MG, QUEEN_EG),
+                king: table(KING_MG, KING_EG),
+            },
+            pawns: PawnParams {
+                doubled: t(-10, -20),
+                isolated: t(-10, -15),
+                backward: t(-8, -10),
+                connected: [t(0, 0), t(0, 0), t(5, 2), t(8, 4), t(12, 8), t(20, 15), t(35, 30), t(0, 0)],
+                passed: [t(0, 0), t(5, 10), t(5, 15), t(10, 25), t(20, 45), t(35, 75), t(55, 120), t(0, 0)],
+                passed_free_path: [t(0, 0), t(0, 5), t(0, 5), t(5, 10), t(10, 20), t(15, 40), t(25, 70), t(0, 0)],
+            },
+            activity: ActivityParams {
+                mobility: [t(4, 4), t(5, 5), t(2, 4), t(1, 2)],
+                mobility_neutral: [4, 6, 6, 12],
+                king_attack_weight: [2, 2, 3, 5],
+                king_safety: vec![
+                    0, 0, 1, 2, 3, 5, 7, 9, 12, 15, 18, 22, 26, 30, 35, 39,
+                    44, 50, 56, 62, 68, 75, 82, 85, 89, 97, 105, 113, 122, 131, 140, 150,
+                    169, 180, 191, 202, 213, 225, 237, 248, 260, 272, 283, 295, 307, 319, 330, 342,
+                    354, 366, 377, 389, 401, 412, 424, 436, 448, 459, 471, 483, 494, 500,
+                ],
+                pawn_shield: [t(12, 0), t(6, 0)],
+                king_semi_open_file: t(-15, 0),
+                king_open_file: t(-10, 0),
+                bishop_pair: t(30, 50),
+                rook_open_file: t(25, 10),
+                rook_semi_open_file: t(12, 5),
+                rook_seventh_rank: t(20, 30),
+                knight_outpost: t(20, 10),
+            },
+        }
+    }
+}
+
+// Built-in piece-square tables, laid out like the ones in EvalParams
+
+const KING_MG: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [20, 20, 0, 0, 0, 0, 20, 20],
+    [20, 30, 10, 0, 0, 10, 30, 20],
+];
+
+const KING_EG: [[i32; 8]; 8] = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10, 0, 0, -10, -20, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 30, 40, 40, 30, -10, -30],
+    [-30, -10, 20, 30, 30, 20, -10, -30],
+    [-30, -30, 0, 0, 0, 0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+const QUEEN_MG: [[i32; 8]; 8] = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [-10, 5, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 0, 0, 0, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+const QUEEN_EG: [[i32; 8]; 8] = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-10, 5, 10, 10, 10, 10, 5, -10],
+    [-5, 5, 10, 15, 15, 10, 5, -5],
+    [-5, 5, 10, 15, 15, 10, 5, -5],
+    [-10, 5, 10, 10, 10, 10, 5, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+const ROOK_MG: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 10, 10, 10, 10, 10, 10, 5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [0, 0, 0, 5, 5, 0, 0, 0],
+];
+
+const ROOK_EG: [[i32; 8]; 8] = [
+    [5, 5, 5, 5, 5, 5, 5, 5],
+    [10, 10, 10, 10, 10, 10, 10, 10],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+];
+
+const BISHOP_MG: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 5, 5, 10, 10, 5, 5, -10],
+    [-10, 0, 10, 10, 10, 10, 0, -10],
+    [-10, 10, 10, 10, 10, 10, 10, -10],
+    [-10, 5, 0, 0, 0, 0, 5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+const BISHOP_EG: [[i32; 8]; 8] = [
+    [-15, -10, -10, -10, -10, -10, -10, -15],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-15, -10, -10, -10, -10, -10, -10, -15],
+];
+
+const KNIGHT_MG: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20, 0, 0, 0, 0, -20, -40],
+    [-30, 0, 10, 15, 15, 10, 0, -30],
+    [-30, 5, 15, 20, 20, 15, 5, -30],
+    [-30, 0, 15, 20, 20, 15, 0, -30],
+    [-30, 5, 10, 15, 15, 10, 5, -30],
+    [-40, -20, 0, 5, 5, 0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+const KNIGHT_EG: [[i32; 8]; 8] = [
+    [-40, -30, -20, -20, -20, -20, -30, -40],
+    [-30, -10, 0, 0, 0, 0, -10, -30],
+    [-20, 0, 10, 10, 10, 10, 0, -20],
+    [-20, 0, 10, 15, 15, 10, 0, -20],
+    [-20, 0, 10, 15, 15, 10, 0, -20],
+    [-20, 0, 10, 10, 10, 10, 0, -20],
+    [-30, -10, 0, 0, 0, 0, -10, -30],
+    [-40, -30, -20, -20, -20, -20, -30, -40],
+];
+
+const PAWN_MG: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [10, 10, 20, 30, 30, 20, 10, 10],
+    [5, 5, 10, 25, 25, 10, 5, 5],
+    [0, 0, 0, 20, 20, 0, 0, 0],
+    [5, -5, -10, 0, 0, -10, -5, 5],
+    [5, 15, -10, -20, -20, 10, 15, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+// Passed pawns get most of their endgame value from the pawn structure terms
+const PAWN_EG: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [30, 30, 30, 30, 30, 30, 30, 30],
+    [20, 20, 20, 20, 20, 20, 20, 20],
+    [10, 10, 10, 10, 10, 10, 10, 10],
+    [5, 5, 5, 5, 5, 5, 5, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
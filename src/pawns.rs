@@ -0,0 +1,181 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::evaluation::Tapered;
+use crate::params::PawnParams;
+use crate::pieces::{
+    PieceColor,
+    PieceKind,
+};
+
+/// Rank counted from the pawn's own side, 0 for rank 1 up to 7 for rank 8
+#[inline]
+fn relative_rank(color: PieceColor, y: usize) -> usize {
+    match color {
+        PieceColor::White => 7 - y,
+        PieceColor::Black => y,
+    }
+}
+
+/// Row offset of one step towards promotion
+#[inline]
+fn forward(color: PieceColor) -> i32 {
+    match color {
+        PieceColor::White => -1,
+        PieceColor::Black => 1,
+    }
+}
+
+/// Everything about the pawns that depends only on where the pawns are
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PawnStructure {
+    /// Doubled, isolated, backward, connected and passed pawn terms, from White's point of view
+    pub score: Tapered,
+    /// Squares (`y * 8 + x` bits) of the passed pawns of each color, indexed by `PieceColor as usize`
+    pub passed: [u64; 2],
+}
+
+impl PawnStructure {
+    pub fn analyze(board: &Board, params: &PawnParams) -> Self {
+        // Pawn occupancy per color as [file][row]
+        let mut pawns = [[[false; 8]; 8]; 2];
+        for (y, row) in board.board.iter().enumerate() {
+            for (x, square) in row.iter().enumerate() {
+                if let Square::Occupied(piece) = square {
+                    if piece.kind == PieceKind::Pawn {
+                        pawns[piece.color as usize][x][y] = true;
+                    }
+                }
+            }
+        }
+
+        let mut structure = PawnStructure::default();
+        for color in [PieceColor::White, PieceColor::Black] {
+            let own = &pawns[color as usize];
+            let enemy = &pawns[color.opposite() as usize];
+            let step = forward(color);
+            let own_at = |x: i32, y: i32| (0..8).contains(&x) && (0..8).contains(&y) && own[x as usize][y as usize];
+            let enemy_at = |x: i32, y: i32| (0..8).contains(&x) && (0..8).contains(&y) && enemy[x as usize][y as usize];
+            // Whether row `a` is further up the board than row `b` for this color
+            let ahead = |a: i32, b: i32| (a - b) * step > 0;
+
+            let mut score = Tapered::default();
+            for x in 0..8i32 {
+                let on_file = own[x as usize].iter().filter(|p| **p).count() as i32;
+                if on_file > 1 {
+                    score += params.doubled * (on_file - 1);
+                }
+
+                for y in 0..8i32 {
+                    if !own[x as usize][y as usize] {
+                        continue;
+                    }
+                    let rank = relative_rank(color, y as usize);
+                    let neighbours: Vec<i32> = [x - 1, x + 1]
+                        .iter()
+                        .filter(|&&f| (0..8).contains(&f))
+                        .flat_map(|&f| (0..8).filter(move |&r| own[f as usize][r as usize]))
+                        .collect();
+
+                    if neighbours.is_empty() {
+                        score += params.isolated;
+                    } else {
+                        let supported = own_at(x - 1, y - step) || own_at(x + 1, y - step);
+                        let phalanx = own_at(x - 1, y) || own_at(x + 1, y);
+                        if supported || phalanx {
+                            score += params.connected[rank];
+                        } else if neighbours.iter().all(|&r| ahead(r, y))
+                            && (enemy_at(x - 1, y + 2 * step) || enemy_at(x + 1, y + 2 * step))
+                        {
+                            // Every neighbour has already advanced past it and an enemy pawn controls
+                            // the square in front, so it can neither be defended nor safely pushed
+                            score += params.backward;
+                        }
+                    }
+
+                    let blocked = (x - 1..=x + 1).any(|f| (0..8).any(|r| ahead(r, y) && enemy_at(f, r)));
+                    // Only the front pawn of a doubled pair counts as passed
+                    let behind_own = (0..8).any(|r| ahead(r, y) && own_at(x, r));
+                    if !blocked && !behind_own {
+                        score += params.passed[rank];
+                        structure.passed[color as usize] |= 1 << (y * 8 + x);
+                    }
+                }
+            }
+
+            match color {
+                PieceColor::White => structure.score += score,
+                PieceColor::Black => structure.score -= score,
+            }
+        }
+        structure
+    }
+
+    /// Passed pawn terms that depend on the other pieces and so can't be cached, from White's point of view
+    pub fn passed_pawn_path_bonus(&self, board: &Board, params: &PawnParams) -> Tapered {
+        let mut score = Tapered::default();
+        for color in [PieceColor::White, PieceColor::Black] {
+            let mut passed = self.passed[color as usize];
+            while passed != 0 {
+                let square = passed.trailing_zeros() as usize;
+                passed &= passed - 1;
+                let (x, y) = (square % 8, square / 8);
+                let path_free = match color {
+                    PieceColor::White => (0..y).all(|r| board.piece_at(Coord { x, y: r }).is_none()),
+                    PieceColor::Black => (y + 1..8).all(|r| board.piece_at(Coord { x, y: r }).is_none()),
+                };
+                if path_free {
+                    let bonus = params.passed_free_path[relative_rank(color, y)];
+                    match color {
+                        PieceColor::White => score += bonus,
+                        PieceColor::Black => score -= bonus,
+                    }
+                }
+            }
+        }
+        score
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct PawnEntry {
+    key: u64,
+    structure: PawnStructure,
+}
+
+/// Cache of [`PawnStructure`]s keyed on pawn placement. Pawn structures change rarely during a search
+/// so most lookups hit.
+pub struct PawnHashTable {
+    entries: Vec<PawnEntry>,
+}
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+impl Default for PawnHashTable {
+    fn default() -> Self {
+        PawnHashTable::new()
+    }
+}
+
+impl PawnHashTable {
+    pub fn new() -> Self {
+        PawnHashTable { entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE] }
+    }
+
+    /// Entries aren't tagged with the parameters they were computed with, so a table must stick to one set
+    pub fn probe(&mut self, board: &Board, params: &PawnParams) -> PawnStructure {
+        // Empty slots have key 0, which is also the key (and the correct structure) of a board without pawns
+        let key = board.pawn_key();
+        let entry = &mut self.entries[(key as usize) & (PAWN_TABLE_SIZE - 1)];
+        if entry.key != key {
+            *entry = PawnEntry { key, structure: PawnStructure::analyze(board, params) };
+        }
+        entry.structure
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(PawnEntry::default());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::board::{
+    Board,
+    Coord,
+    Move,
+};
+use crate::datagen::GameResult;
+use crate::pieces::{
+    PieceColor,
+    PieceKind,
+};
+use std::{fmt, io::{self, BufRead}};
+
+#[derive(Debug)]
+pub enum PgnError {
+    Io(io::Error),
+    /// Game `game` (counting from 1) of the file couldn't be read
+    Parse { game: usize, desc: String },
+}
+pub type Result<T> = std::result::Result<T, PgnError>;
+
+impl std::error::Error for PgnError {}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::Io(err) => write!(f, "Could not read games: {err}"),
+            PgnError::Parse { game, desc } => write!(f, "Invalid game {game}: {desc}"),
+        }
+    }
+}
+
+impl From<io::Error> for PgnError {
+    fn from(err: io::Error) -> Self {
+        PgnError::Io(err)
+    }
+}
+
+/// One game of a PGN file: its tags and the moves of the main line, still in SAN.
+/// Comments, variations and annotations are dropped.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    /// From the termination marker at the end of the moves, `None` for `*` or when it's missing
+    pub result: Option<GameResult>,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Rating of the player of `color` from the `WhiteElo` or `BlackElo` tag
+    pub fn rating(&self, color: PieceColor) -> Option<u32> {
+        let tag = match color {
+            PieceColor::White => "WhiteElo",
+            PieceColor::Black => "BlackElo",
+        };
+        self.tag(tag)?.parse().ok()
+    }
+
+    /// The `FEN` tag if there is one, otherwise the standard starting position
+    pub fn start_position(&self) -> std::result::Result<Board, String> {
+        match self.tag("FEN") {
+            Some(fen) => Board::from_fen(fen).map_err(|err| err.to_string()),
+            None => Ok(Board::default()),
+        }
+    }
+}
+
+fn parse_result(token: &str) -> Option<Option<GameResult>> {
+    match token {
+        "1-0" => Some(Some(GameResult::WhiteWin)),
+        "0-1" => Some(Some(GameResult::BlackWin)),
+        "1/2-1/2" => Some(Some(GameResult::Draw)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+/// Reads `[Name "Value"]`, undoing the `\"` and `\\` escapes of the value
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?.trim();
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Some((name.to_string(), unescaped))
+}
+
+/// Iterator over the games of a PGN file, see [`read_games`]
+pub struct PgnReader<R> {
+    input: R,
+    games: usize,
+    /// A line that already belongs to the next game
+    pending: Option<String>,
+}
+
+/// Reads the games of a PGN file one at a time
+pub fn read_games<R: BufRead>(input: R) -> PgnReader<R> {
+    PgnReader { input, games: 0, pending: None }
+}
+
+impl<R: BufRead> PgnReader<R> {
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        let mut line = String::new();
+        match self.input.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+
+    fn read_game(&mut self) -> Result<Option<PgnGame>> {
+        let mut game = PgnGame::default();
+        let mut started = false;
+        let mut in_comment = false;
+        let mut variation_depth = 0usize;
+        let parse_error = |games: usize, desc: String| PgnError::Parse { game: games + 1, desc };
+
+        while let Some(line) = self.next_line()? {
+            let trimmed = line.trim();
+            if !in_comment && trimmed.starts_with('[') {
+                if !game.moves.is_empty() {
+                    // The previous game had no termination marker
+                    self.pending = Some(line);
+                    break;
+                }
+                let tag = parse_tag(trimmed).ok_or_else(|| parse_error(self.games, format!("Invalid tag {trimmed}")))?;
+                game.tags.push(tag);
+                started = true;
+                continue;
+            }
+            if !in_comment && trimmed.starts_with('%') {
+                continue;
+            }
+
+            let mut tokens = Vec::new();
+            let mut token = String::new();
+            for c in line.chars() {
+                if in_comment {
+                    in_comment = c != '}';
+                    continue;
+                }
+                match c {
+                    '{' => in_comment = true,
+                    ';' => break,
+                    '(' => variation_depth += 1,
+                    ')' => variation_depth = variation_depth.saturating_sub(1),
+                    c if c.is_whitespace() => {}
+                    c => {
+                        if variation_depth == 0 {
+                            token.push(c);
+                        }
+                        continue;
+                    }
+                }
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            if !token.is_empty() {
+                tokens.push(token);
+            }
+
+            for token in tokens {
+                started = true;
+                if let Some(result) = parse_result(&token) {
+                    game.result = result;
+                    self.games += 1;
+                    return Ok(Some(game));
+                }
+                // Move numbers may be glued to the move, as in `1.e4` or `12...Nf6`
+                let san = match token.rfind('.') {
+                    Some(dot) if token.starts_with(|c: char| c.is_ascii_digit()) => &token[dot + 1..],
+                    _ => token.as_str(),
+                };
+                if san.is_empty() || san.starts_with('$') || san.chars().all(|c| c == '!' || c == '?') {
+                    continue;
+                }
+                game.moves.push(san.to_string());
+            }
+        }
+
+        if !started {
+            return Ok(None);
+        }
+        self.games += 1;
+        Ok(Some(game))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_game().transpose()
+    }
+}
+
+fn parse_kind(c: char) -> Option<PieceKind> {
+    match c {
+        'N' => Some(PieceKind::Knight),
+        'B' => Some(PieceKind::Bishop),
+        'R' => Some(PieceKind::Rook),
+        'Q' => Some(PieceKind::Queen),
+        'K' => Some(PieceKind::King),
+        _ => None,
+    }
+}
+
+/// Finds the legal move written as `san` in standard algebraic notation, such as `e4`, `Nbd7`, `exd5`,
+/// `O-O` or `e8=Q+`. Underpromotions come back with their piece in `promote`, but only promotions to a
+/// queen can be played on a [`Board`].
+pub fn parse_san(board: &Board, san: &str) -> std::result::Result<Move, String> {
+    let invalid = || format!("Invalid move {san}");
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal = board.list_all_valid_moves();
+
+    let back_rank = match board.turn {
+        PieceColor::White => 7,
+        PieceColor::Black => 0,
+    };
+    let castle_to = match text {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None,
+    };
+    if let Some(x) = castle_to {
+        let mv = Move::new(Coord { x: 4, y: back_rank }, Coord { x, y: back_rank }, None);
+        let is_king = board.piece_at(mv.from).is_some_and(|piece| piece.kind == PieceKind::King);
+        return match is_king && legal.contains(&mv) {
+            true => Ok(mv),
+            false => Err(format!("Illegal move {san}")),
+        };
+    }
+
+    let mut chars: Vec<char> = text.chars().filter(|&c| c != 'x' && c != '-').collect();
+    let mut promote = None;
+    if let Some(&last) = chars.last() {
+        if let Some(kind) = parse_kind(last).filter(|kind| *kind != PieceKind::King) {
+            promote = Some(kind);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+    let kind = match chars.first().copied().and_then(parse_kind) {
+        Some(kind) => {
+            chars.remove(0);
+            kind
+        }
+        None => PieceKind::Pawn,
+    };
+    if chars.len() < 2 || chars.len() > 4 {
+        return Err(invalid());
+    }
+    let (disambiguation, target) = chars.split_at(chars.len() - 2);
+    let to = Coord::from_notation(&target.iter().collect::<String>()).map_err(|_| invalid())?;
+    let mut from_file = None;
+    let mut from_rank = None;
+    for &c in disambiguation {
+        match c {
+            'a'..='h' => from_file = Some(c as usize - 'a' as usize),
+            '1'..='8' => from_rank = Some(7 - (c as usize - '1' as usize)),
+            _ => return Err(invalid()),
+        }
+    }
+    let mut candidates = legal.into_iter().filter(|mv| {
+        mv.to == to
+            && board.piece_at(mv.from).is_some_and(|piece| piece.kind == kind)
+            && from_file.is_none_or(|x| mv.from.x == x)
+            && from_rank.is_none_or(|y| mv.from.y == y)
+    });
+    match (candidates.next(), candidates.next()) {
+        (Some(mv), None) => match promote {
+            None | Some(PieceKind::Queen) => Ok(mv),
+            // Only pawns reaching the last rank promote
+            Some(_) if kind != PieceKind::Pawn || (to.y != 0 && to.y != 7) => Err(invalid()),
+            Some(_) => Ok(Move { promote, ..mv }),
+        },
+        (Some(_), Some(_)) => Err(format!("Ambiguous move {san}")),
+        (None, _) => Err(format!("Illegal move {san}")),
+    }
+}
@@ -79,6 +79,22 @@ impl Piece {
         }
     }
 
+    /// Letter used in FEN and algebraic notation, upper case for White
+    pub fn fen_char(&self) -> char {
+        let c = match self.kind {
+            Pawn => 'p',
+            Knight => 'n',
+            Bishop => 'b',
+            Rook => 'r',
+            Queen => 'q',
+            King => 'k',
+        };
+        match self.color {
+            PieceColor::White => c.to_ascii_uppercase(),
+            PieceColor::Black => c,
+        }
+    }
+
     pub fn is_valid_piece_move(&self, from: Coord, to: Coord) -> bool {
         // Check that coords are from (0,0) to (7,7)
         from.x < 8 && from.y < 8 && to.x < 8 && to.y < 8 && from != to && 
@@ -0,0 +1,528 @@
+use crate::board::{
+    Board,
+    Coord,
+    Move,
+    Square,
+};
+use crate::engine::is_capture;
+use crate::pieces::{
+    Piece,
+    PieceColor,
+    PieceKind,
+};
+use crate::syzygy::{
+    has_castling_rights,
+    Tablebase,
+    Wdl,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RetrogradeError {
+    /// Not a material signature such as `KRvK`, or one that can't be generated
+    Material(String),
+    /// Some mate is further away than a table can store
+    TooDeep(String),
+}
+pub type Result<T> = std::result::Result<T, RetrogradeError>;
+
+impl std::error::Error for RetrogradeError {}
+
+impl fmt::Display for RetrogradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetrogradeError::Material(desc) => write!(f, "Invalid material: {desc}"),
+            RetrogradeError::TooDeep(name) => write!(f, "Mates in {name} are too long to store"),
+        }
+    }
+}
+
+/// Distance to mate in plies with best play, from the point of view of the side to move.
+/// `Loss(0)` is a side that is already mated.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Dtm {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+/// Most pieces, kings included, a generated table can hold
+pub const MAX_PIECES: usize = 4;
+
+/// Stored for positions that can't occur, such as pieces on the same square
+const ILLEGAL: u8 = u8::MAX;
+/// Longest mate in plies a table can store, values are the plies plus one
+const MAX_PLIES: usize = ILLEGAL as usize - 2;
+/// Move count of positions that can never be lost
+const BLOCKED: u8 = u8::MAX;
+
+/// The pieces besides the kings, side 0 being the one written first in the name and White in the table
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Material {
+    sides: [Vec<PieceKind>; 2],
+}
+
+fn piece_letter(kind: PieceKind) -> char {
+    match kind {
+        PieceKind::Pawn => 'P',
+        PieceKind::Knight => 'N',
+        PieceKind::Bishop => 'B',
+        PieceKind::Rook => 'R',
+        PieceKind::Queen => 'Q',
+        PieceKind::King => 'K',
+    }
+}
+
+impl Material {
+    fn new(mut sides: [Vec<PieceKind>; 2]) -> Self {
+        for pieces in &mut sides {
+            pieces.sort_by_key(|&kind| std::cmp::Reverse(kind as u8));
+        }
+        Material { sides }
+    }
+
+    /// Reads a signature such as `KBNvK`
+    fn parse(name: &str) -> Result<Self> {
+        let invalid = |desc: &str| RetrogradeError::Material(format!("{name}: {desc}"));
+        let (first, second) = name.split_once('v').ok_or_else(|| invalid("expected two sides separated by v"))?;
+        let mut sides = [Vec::new(), Vec::new()];
+        for (side, letters) in [first, second].into_iter().enumerate() {
+            let mut kings = 0;
+            for c in letters.chars() {
+                match c {
+                    'K' => kings += 1,
+                    'Q' => sides[side].push(PieceKind::Queen),
+                    'R' => sides[side].push(PieceKind::Rook),
+                    'B' => sides[side].push(PieceKind::Bishop),
+                    'N' => sides[side].push(PieceKind::Knight),
+                    'P' => sides[side].push(PieceKind::Pawn),
+                    _ => return Err(invalid("unknown piece")),
+                }
+            }
+            if kings != 1 {
+                return Err(invalid("each side needs one king"));
+            }
+        }
+        let material = Material::new(sides);
+        if material.piece_count() > MAX_PIECES {
+            return Err(invalid(&format!("at most {MAX_PIECES} pieces are supported")));
+        }
+        // Tables don't know about en passant, which needs pawns on both sides
+        if material.sides.iter().all(|pieces| pieces.contains(&PieceKind::Pawn)) {
+            return Err(invalid("only one side may have pawns"));
+        }
+        Ok(material)
+    }
+
+    fn of(board: &Board) -> Self {
+        let mut sides = [Vec::new(), Vec::new()];
+        for piece in board.board.iter().flatten().filter_map(|square| match square {
+            Square::Occupied(piece) if piece.kind != PieceKind::King => Some(piece),
+            _ => None,
+        }) {
+            sides[piece.color as usize].push(piece.kind);
+        }
+        Material::new(sides)
+    }
+
+    fn flipped(&self) -> Self {
+        Material { sides: [self.sides[1].clone(), self.sides[0].clone()] }
+    }
+
+    fn name(&self) -> String {
+        let side = |pieces: &[PieceKind]| -> String { std::iter::once('K').chain(pieces.iter().map(|&kind| piece_letter(kind))).collect() };
+        format!("{}v{}", side(&self.sides[0]), side(&self.sides[1]))
+    }
+
+    fn piece_count(&self) -> usize {
+        2 + self.sides[0].len() + self.sides[1].len()
+    }
+
+    /// Only the kings are left, which is a draw
+    fn is_bare(&self) -> bool {
+        self.sides.iter().all(|pieces| pieces.is_empty())
+    }
+
+    /// What a capture or a promotion can turn this material into
+    fn successors(&self) -> Vec<Material> {
+        let mut successors = Vec::new();
+        for side in 0..2 {
+            for (i, &kind) in self.sides[side].iter().enumerate() {
+                let mut sides = self.sides.clone();
+                sides[side].remove(i);
+                successors.push(Material::new(sides.clone()));
+                if kind == PieceKind::Pawn {
+                    sides[side].push(PieceKind::Queen);
+                    successors.push(Material::new(sides));
+                }
+            }
+        }
+        successors
+    }
+}
+
+fn side_color(side: usize) -> PieceColor {
+    if side == 0 { PieceColor::White } else { PieceColor::Black }
+}
+
+fn in_check(board: &Board, color: PieceColor) -> bool {
+    let king = match color {
+        PieceColor::White => board.white_king,
+        PieceColor::Black => board.black_king,
+    };
+    board.attacked_by(color.opposite()) & (1 << (king.y * 8 + king.x)) != 0
+}
+
+/// Captures and promotions lead out of the table
+fn leaves_table(board: &Board, mv: Move) -> bool {
+    let pawn = board.piece_at(mv.from).is_some_and(|piece| piece.kind == PieceKind::Pawn);
+    is_capture(board, mv) || (pawn && (mv.to.y == 0 || mv.to.y == 7))
+}
+
+/// Squares a pawn of `color` on `to` could have come from without capturing, as a bitmask
+fn pawn_origins(color: PieceColor, to: usize, occupied: u64) -> u64 {
+    let empty = |sq: usize| occupied & (1 << sq) == 0;
+    let (y, mut origins) = (to / 8, 0);
+    match color {
+        // White pawns move towards y = 0
+        PieceColor::White if y <= 5 && empty(to + 8) => {
+            origins |= 1 << (to + 8);
+            if y == 4 && empty(to + 16) {
+                origins |= 1 << (to + 16);
+            }
+        }
+        PieceColor::Black if y >= 2 && empty(to - 8) => {
+            origins |= 1 << (to - 8);
+            if y == 3 && empty(to - 16) {
+                origins |= 1 << (to - 16);
+            }
+        }
+        _ => {}
+    }
+    origins
+}
+
+/// Squares of the pieces in table order, as `y * 8 + x`
+type Placement = [usize; MAX_PIECES];
+
+/// Values of every placement of one material with either side to move. The first king is mirrored
+/// into the a1-d4 quarter of the board, or onto files a-d when there are pawns, which leaves no
+/// position symmetric to itself.
+struct Table {
+    /// Side and kind of each piece, the two kings first
+    pieces: Vec<(usize, PieceKind)>,
+    has_pawns: bool,
+    /// 0 for draws, otherwise the distance to mate in plies plus one. Odd distances are wins.
+    values: Vec<u8>,
+}
+
+impl Table {
+    fn new(material: &Material) -> Self {
+        let mut pieces = vec![(0, PieceKind::King), (1, PieceKind::King)];
+        for (side, kinds) in material.sides.iter().enumerate() {
+            pieces.extend(kinds.iter().map(|&kind| (side, kind)));
+        }
+        let has_pawns = pieces.iter().any(|&(_, kind)| kind == PieceKind::Pawn);
+        let mut table = Table { pieces, has_pawns, values: Vec::new() };
+        table.values = vec![0; 2 * table.king_squares() * 64usize.pow(table.pieces.len() as u32 - 1)];
+        table
+    }
+
+    fn king_squares(&self) -> usize {
+        if self.has_pawns { 32 } else { 16 }
+    }
+
+    fn index(&self, mut squares: Placement, stm: usize) -> usize {
+        let n = self.pieces.len();
+        if squares[0] % 8 > 3 {
+            squares[..n].iter_mut().for_each(|sq| *sq ^= 7);
+        }
+        if !self.has_pawns && squares[0] / 8 > 3 {
+            squares[..n].iter_mut().for_each(|sq| *sq ^= 56);
+        }
+        let king = squares[0] / 8 * 4 + squares[0] % 8;
+        squares[1..n].iter().fold(stm * self.king_squares() + king, |idx, &sq| idx * 64 + sq)
+    }
+
+    fn placement(&self, mut idx: usize) -> (Placement, usize) {
+        let mut squares = [0; MAX_PIECES];
+        for sq in squares[1..self.pieces.len()].iter_mut().rev() {
+            *sq = idx % 64;
+            idx /= 64;
+        }
+        let king = idx % self.king_squares();
+        squares[0] = king / 4 * 8 + king % 4;
+        (squares, idx / self.king_squares())
+    }
+
+    /// `None` when the placement isn't a legal position
+    fn board(&self, squares: &Placement, stm: usize) -> Option<Board> {
+        let mut board = Board::empty();
+        board.white_castle = (false, false);
+        board.black_castle = (false, false);
+        for (&(side, kind), &sq) in self.pieces.iter().zip(squares) {
+            let coord = Coord { x: sq % 8, y: sq / 8 };
+            if board.board[coord.y][coord.x] != Square::Empty || (kind == PieceKind::Pawn && (coord.y == 0 || coord.y == 7)) {
+                return None;
+            }
+            let color = side_color(side);
+            board.board[coord.y][coord.x] = Square::Occupied(Piece { color, kind });
+            match (kind, color) {
+                (PieceKind::King, PieceColor::White) => board.white_king = coord,
+                (PieceKind::King, PieceColor::Black) => board.black_king = coord,
+                _ => {}
+            }
+        }
+        board.turn = side_color(stm);
+        // The side that just moved can't have left its king in check
+        if in_check(&board, board.turn.opposite()) {
+            return None;
+        }
+        let check = in_check(&board, board.turn);
+        board.in_check = if stm == 0 { (check, false) } else { (false, check) };
+        Some(board)
+    }
+
+    /// Positions one non-capturing move before `board`, as indices
+    fn predecessors(&self, board: &Board, squares: &Placement, stm: usize) -> Vec<usize> {
+        let n = self.pieces.len();
+        let mover = 1 - stm;
+        let occupied = squares[..n].iter().fold(0u64, |mask, &sq| mask | 1 << sq);
+        let mut predecessors = Vec::new();
+        for (i, &(side, kind)) in self.pieces.iter().enumerate() {
+            if side != mover {
+                continue;
+            }
+            let to = squares[i];
+            let mut origins = match kind {
+                PieceKind::Pawn => pawn_origins(side_color(side), to, occupied),
+                _ => board.attacks_from(Coord { x: to % 8, y: to / 8 }) & !occupied,
+            };
+            while origins != 0 {
+                let from = origins.trailing_zeros() as usize;
+                origins &= origins - 1;
+                let mut previous = *board;
+                previous.board[to / 8][to % 8] = Square::Empty;
+                previous.board[from / 8][from % 8] = Square::Occupied(Piece { color: side_color(side), kind });
+                match (kind, side) {
+                    (PieceKind::King, 0) => previous.white_king = Coord { x: from % 8, y: from / 8 },
+                    (PieceKind::King, _) => previous.black_king = Coord { x: from % 8, y: from / 8 },
+                    _ => {}
+                }
+                if in_check(&previous, side_color(stm)) {
+                    continue;
+                }
+                let mut previous_squares = *squares;
+                previous_squares[i] = from;
+                predecessors.push(self.index(previous_squares, mover));
+            }
+        }
+        predecessors
+    }
+
+    /// Retrograde analysis: mates are found first, then positions are resolved in order of distance.
+    /// A position is won as soon as one move reaches a lost position, and lost once every move
+    /// reaches a won one. Captures and promotions are looked up in `tablebase`.
+    fn generate(material: &Material, tablebase: &RetrogradeTablebase) -> Result<Self> {
+        let mut table = Table::new(material);
+        let len = table.values.len();
+        // In-table moves not yet known to lose, and the longest loss through captures or promotions
+        let mut remaining = vec![0u8; len];
+        let mut exit_loss = vec![0u8; len];
+        // Positions to resolve at each distance, a position may be listed more than once
+        let mut pending: Vec<Vec<u32>> = Vec::new();
+        let schedule = |pending: &mut Vec<Vec<u32>>, plies: usize, idx: usize| -> Result<()> {
+            if plies > MAX_PLIES {
+                return Err(RetrogradeError::TooDeep(material.name()));
+            }
+            if pending.len() <= plies {
+                pending.resize_with(plies + 1, Vec::new);
+            }
+            pending[plies].push(idx as u32);
+            Ok(())
+        };
+
+        for idx in 0..len {
+            let (squares, stm) = table.placement(idx);
+            let Some(board) = table.board(&squares, stm) else {
+                table.values[idx] = ILLEGAL;
+                continue;
+            };
+            let moves = board.list_all_valid_moves();
+            if moves.is_empty() {
+                match board.get_check() {
+                    true => schedule(&mut pending, 0, idx)?,
+                    false => remaining[idx] = BLOCKED,
+                }
+                continue;
+            }
+
+            let (mut quickest_win, mut holds, mut longest_loss, mut count) = (None, false, 0, 0);
+            for mv in moves {
+                if !leaves_table(&board, mv) {
+                    count += 1;
+                    continue;
+                }
+                let mut child = board;
+                child.do_move_from_coord(mv).unwrap();
+                let outcome = tablebase
+                    .probe_dtm(&child)
+                    .ok_or_else(|| RetrogradeError::Material(format!("{} is missing", Material::of(&child).name())))?;
+                match outcome {
+                    Dtm::Loss(plies) => quickest_win = Some(quickest_win.map_or(plies + 1, |win: u32| win.min(plies + 1))),
+                    Dtm::Draw => holds = true,
+                    Dtm::Win(plies) => longest_loss = longest_loss.max(plies as usize + 1),
+                }
+            }
+            if let Some(plies) = quickest_win {
+                schedule(&mut pending, plies as usize, idx)?;
+            }
+            if quickest_win.is_some() || holds {
+                remaining[idx] = BLOCKED;
+            } else if count == 0 {
+                schedule(&mut pending, longest_loss, idx)?;
+            } else {
+                remaining[idx] = count;
+                exit_loss[idx] = longest_loss.min(MAX_PLIES + 1) as u8;
+            }
+        }
+
+        let mut plies = 0;
+        while plies < pending.len() {
+            for idx in std::mem::take(&mut pending[plies]) {
+                let idx = idx as usize;
+                if table.values[idx] != 0 {
+                    continue;
+                }
+                table.values[idx] = plies as u8 + 1;
+                let (squares, stm) = table.placement(idx);
+                let board = table.board(&squares, stm).unwrap();
+                for previous in table.predecessors(&board, &squares, stm) {
+                    if table.values[previous] != 0 {
+                        continue;
+                    }
+                    if plies.is_multiple_of(2) {
+                        schedule(&mut pending, plies + 1, previous)?;
+                    } else if remaining[previous] != BLOCKED {
+                        remaining[previous] -= 1;
+                        if remaining[previous] == 0 {
+                            schedule(&mut pending, (plies + 1).max(exit_loss[previous] as usize), previous)?;
+                        }
+                    }
+                }
+            }
+            plies += 1;
+        }
+        Ok(table)
+    }
+
+    /// `flip` swaps the colors and mirrors the ranks, for boards where Black has the table's first side
+    fn probe(&self, board: &Board, flip: bool) -> Option<Dtm> {
+        let n = self.pieces.len();
+        let mut squares = [0; MAX_PIECES];
+        let mut placed = [false; MAX_PIECES];
+        for (y, row) in board.board.iter().enumerate() {
+            for (x, square) in row.iter().enumerate() {
+                if let Square::Occupied(piece) = square {
+                    let side = (piece.color == PieceColor::Black) as usize ^ flip as usize;
+                    let slot = (0..n).find(|&i| !placed[i] && self.pieces[i] == (side, piece.kind))?;
+                    placed[slot] = true;
+                    squares[slot] = (y * 8 + x) ^ if flip { 56 } else { 0 };
+                }
+            }
+        }
+        let stm = (board.turn == PieceColor::Black) as usize ^ flip as usize;
+        match self.values[self.index(squares, stm)] {
+            0 => Some(Dtm::Draw),
+            ILLEGAL => None,
+            value => {
+                let plies = value as u32 - 1;
+                Some(if plies % 2 == 1 { Dtm::Win(plies) } else { Dtm::Loss(plies) })
+            }
+        }
+    }
+}
+
+/// Endgame databases generated in memory by retrograde analysis, with exact distances to mate for
+/// every position. Tables cover up to [`MAX_PIECES`] pieces with pawns on at most one side, and
+/// follow the board in promoting to a queen only. The fifty-move rule is ignored.
+#[derive(Default)]
+pub struct RetrogradeTablebase {
+    tables: HashMap<String, Table>,
+}
+
+impl RetrogradeTablebase {
+    pub fn new() -> Self {
+        RetrogradeTablebase::default()
+    }
+
+    /// Generates the table for a material signature such as `KRvK` or `KBNvK`, along with every
+    /// smaller table its captures and promotions lead to. Tables already present are kept.
+    pub fn generate(&mut self, name: &str) -> Result<()> {
+        self.generate_material(&Material::parse(name)?)
+    }
+
+    fn generate_material(&mut self, material: &Material) -> Result<()> {
+        if material.is_bare() || self.find(material).is_some() {
+            return Ok(());
+        }
+        for successor in material.successors() {
+            self.generate_material(&successor)?;
+        }
+        let table = Table::generate(material, self)?;
+        self.tables.insert(material.name(), table);
+        Ok(())
+    }
+
+    fn find(&self, material: &Material) -> Option<(&Table, bool)> {
+        match self.tables.get(&material.name()) {
+            Some(table) => Some((table, false)),
+            None => self.tables.get(&material.flipped().name()).map(|table| (table, true)),
+        }
+    }
+
+    /// Signatures of the generated tables
+    pub fn tables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// `None` when no table covers the position or it has castling rights
+    pub fn probe_dtm(&self, board: &Board) -> Option<Dtm> {
+        if has_castling_rights(board) {
+            return None;
+        }
+        let material = Material::of(board);
+        if material.is_bare() {
+            return Some(Dtm::Draw);
+        }
+        let (table, flip) = self.find(&material)?;
+        table.probe(board, flip)
+    }
+}
+
+impl Tablebase for RetrogradeTablebase {
+    fn max_pieces(&self) -> usize {
+        self.tables.values().map(|table| table.pieces.len()).max().unwrap_or(0)
+    }
+
+    fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        Some(match self.probe_dtm(board)? {
+            Dtm::Win(_) => Wdl::Win,
+            Dtm::Draw => Wdl::Draw,
+            Dtm::Loss(_) => Wdl::Loss,
+        })
+    }
+
+    /// Distance to mate, which is never shorter than the distance to zeroing. Wins it puts within the
+    /// fifty-move rule are real, and following it always makes progress.
+    fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        Some(match self.probe_dtm(board)? {
+            Dtm::Win(plies) => plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => -(plies.max(1) as i32),
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use crate::board::{
+    Board,
+    Move,
+};
+use crate::engine::{
+    SearchLimits,
+    SearchResult,
+};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Full strength, no handicap at all
+pub const MAX_LEVEL: u8 = 20;
+/// Elo range the levels are spread over. The ratings are rough estimates, not measured against anything.
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+/// Lines searched to pick a suboptimal move from
+const CANDIDATE_LINES: usize = 4;
+/// Largest score gap between the candidate lines that still adds randomness, one pawn
+const MAX_RANDOM_GAP: i32 = 100;
+/// Chance of a random legal move at level 0, falling off quadratically to none at full strength
+const MAX_BLUNDER_CHANCE: f64 = 0.1;
+
+/// Handicap for playing against weaker players. Below [`MAX_LEVEL`] the search is cut short, several
+/// lines are searched and one of them is picked at random, favouring those that score close to the
+/// best, and now and then a random move is played outright.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Skill {
+    level: u8,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill { level: MAX_LEVEL }
+    }
+}
+
+impl Skill {
+    /// `level` from 0 to [`MAX_LEVEL`], higher levels are capped
+    pub fn new(level: u8) -> Self {
+        Skill { level: level.min(MAX_LEVEL) }
+    }
+
+    /// The level closest to playing at `elo`, clamped to [`MIN_ELO`]..=[`MAX_ELO`]
+    pub fn from_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let step = (MAX_ELO - MIN_ELO) / MAX_LEVEL as u32;
+        Skill::new(((elo - MIN_ELO + step / 2) / step) as u8)
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Estimated rating of this level
+    pub fn elo(&self) -> u32 {
+        MIN_ELO + (MAX_ELO - MIN_ELO) * self.level as u32 / MAX_LEVEL as u32
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level == MAX_LEVEL
+    }
+
+    /// `limits` tightened to the depth and nodes this level may search. Infinite searches stay infinite.
+    pub fn limits(&self, limits: SearchLimits) -> SearchLimits {
+        if self.is_full_strength() {
+            return limits;
+        }
+        let depth = 1 + self.level / 2;
+        let nodes = 200 << (self.level / 2);
+        SearchLimits {
+            depth: Some(limits.depth.map_or(depth, |limit| limit.min(depth))),
+            nodes: Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes))),
+            ..limits
+        }
+    }
+
+    /// Lines the search needs to find for [`Skill::choose`] to have something to choose from
+    pub fn multi_pv(&self) -> usize {
+        if self.is_full_strength() { 1 } else { CANDIDATE_LINES }
+    }
+
+    /// Chance of playing a random legal move instead of one of the searched lines
+    pub fn blunder_chance(&self) -> f64 {
+        let weakness = (MAX_LEVEL - self.level) as f64 / MAX_LEVEL as f64;
+        MAX_BLUNDER_CHANCE * weakness * weakness
+    }
+
+    /// Picks the move to play from a search of `board`, ideally one with [`Skill::multi_pv`] lines.
+    /// Every line gets a random bonus, which makes up a larger part of the gap to the best score and
+    /// varies more the lower the level. Book moves, searched to depth 0, are always played.
+    pub fn choose(&self, board: &Board, result: &SearchResult, rng: &mut impl Rng) -> Move {
+        if self.is_full_strength() || result.depth == 0 || result.lines.is_empty() {
+            return result.best_move;
+        }
+        if rng.gen_bool(self.blunder_chance()) {
+            if let Some(&mv) = board.list_all_valid_moves().choose(rng) {
+                return mv;
+            }
+        }
+
+        let weakness = 120 - 2 * self.level as i32;
+        let top = result.lines[0].score;
+        let gap = (top - result.lines[result.lines.len() - 1].score).min(MAX_RANDOM_GAP);
+        let mut best = (result.best_move, i32::MIN);
+        for line in &result.lines {
+            let push = (weakness * (top - line.score) + gap * rng.gen_range(0..weakness)) / 128;
+            if line.score + push >= best.1 {
+                best = (line.moves[0], line.score + push);
+            }
+        }
+        best.0
+    }
+}
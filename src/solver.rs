@@ -0,0 +1,319 @@
+use crate::board::{
+    Board,
+    Move,
+};
+use std::collections::HashMap;
+
+/// Choices for [`solve_mate`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct SolverOptions {
+    /// Only try checking moves for the attacker. Much faster, but keys and continuations that don't give
+    /// check are missed, so a problem proven this way isn't proven unique.
+    pub checks_only: bool,
+}
+
+/// A move of the side forcing the mate, with every defence against it
+#[derive(Clone, PartialEq, Debug)]
+pub struct AttackMove {
+    pub mv: Move,
+    /// Moves until mate with the best defence, this one included
+    pub mate_in: u32,
+    /// Every legal reply, empty when the move mates
+    pub defences: Vec<Defence>,
+}
+
+/// A move of the defending side, with every continuation that still forces mate in time
+#[derive(Clone, PartialEq, Debug)]
+pub struct Defence {
+    pub mv: Move,
+    /// More than one is a dual. Empty when this move ends the problem, as the mate a selfmate forces.
+    pub continuations: Vec<AttackMove>,
+}
+
+/// Outcome of [`solve_mate`] and [`solve_selfmate`]
+#[derive(Clone, PartialEq, Debug)]
+pub struct MateSolution {
+    /// The N of mate in N
+    pub moves: u32,
+    /// Every first move that forces mate in at most `moves`, shortest mates first. Empty when there is no
+    /// forced mate, which the search has then proven.
+    pub keys: Vec<AttackMove>,
+    /// Positions visited
+    pub nodes: u64,
+}
+
+impl MateSolution {
+    pub fn is_solved(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Exactly one key move, as a sound problem has
+    pub fn is_unique(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    /// More than one key move
+    pub fn is_cooked(&self) -> bool {
+        self.keys.len() > 1
+    }
+
+    /// Whether some defence, after any of the keys, can be met by more than one continuation
+    pub fn has_duals(&self) -> bool {
+        fn duals(attack: &AttackMove) -> bool {
+            attack.defences.iter().any(|defence| defence.continuations.len() > 1 || defence.continuations.iter().any(duals))
+        }
+        self.keys.iter().any(duals)
+    }
+}
+
+/// Outcome of [`solve_helpmate`]
+#[derive(Clone, PartialEq, Debug)]
+pub struct HelpmateSolution {
+    /// The N of helpmate in N
+    pub moves: u32,
+    /// Every solution as the moves of both sides in turn, starting with the side that gets mated. In
+    /// move generation order, so solutions sharing a first move are next to each other.
+    pub solutions: Vec<Vec<Move>>,
+    /// Positions visited
+    pub nodes: u64,
+}
+
+impl HelpmateSolution {
+    pub fn is_solved(&self) -> bool {
+        !self.solutions.is_empty()
+    }
+
+    /// Solutions starting with different moves
+    pub fn is_cooked(&self) -> bool {
+        self.solutions.iter().any(|solution| solution[0] != self.solutions[0][0])
+    }
+
+    /// Solutions that start with the same move but part ways later
+    pub fn has_duals(&self) -> bool {
+        self.solutions.windows(2).any(|pair| pair[0][0] == pair[1][0])
+    }
+}
+
+/// Proves or disproves that the side to move on `board` mates in at most `moves` moves whatever the
+/// defence, and returns the full solution tree. Every legal move is tried unless
+/// [`SolverOptions::checks_only`] says otherwise, the fifty-move rule and repetitions are ignored as in
+/// composed problems. Exhaustive, so the time it takes grows steeply with `moves`.
+pub fn solve_mate(board: &Board, moves: u32, options: SolverOptions) -> MateSolution {
+    let mut solver = Solver::new(options);
+    let mut keys = solver.attack_tree(board, moves);
+    keys.sort_by_key(|key| key.mate_in);
+    MateSolution { moves, keys, nodes: solver.nodes }
+}
+
+/// Finds every way for the side to move on `board` and its opponent to cooperate so that the side to move
+/// is mated by the opponent's `moves`-th move, not before. Each side gets `moves` moves.
+pub fn solve_helpmate(board: &Board, moves: u32) -> HelpmateSolution {
+    let mut solver = Solver::new(SolverOptions::default());
+    let solutions = if moves == 0 { Vec::new() } else { solver.help_lines(board, 2 * moves) };
+    HelpmateSolution { moves, solutions, nodes: solver.nodes }
+}
+
+/// Proves or disproves that the side to move on `board` can force its opponent, who tries everything to
+/// avoid it, to deliver mate within `moves` moves, and returns the solution tree as [`solve_mate`] does.
+/// Defences that give the mate end their line without continuations.
+pub fn solve_selfmate(board: &Board, moves: u32) -> MateSolution {
+    let mut solver = Solver::new(SolverOptions::default());
+    let mut keys = solver.selfmate_tree(board, moves);
+    keys.sort_by_key(|key| key.mate_in);
+    MateSolution { moves, keys, nodes: solver.nodes }
+}
+
+/// Search state for solving one problem
+struct Solver {
+    options: SolverOptions,
+    /// Whether the goal can be reached from a position with the given number of moves or plies left, by
+    /// position key. Each solver only ever works on one kind of problem.
+    proven: HashMap<(u64, u32), bool>,
+    nodes: u64,
+}
+
+impl Solver {
+    fn new(options: SolverOptions) -> Self {
+        Solver { options, proven: HashMap::new(), nodes: 0 }
+    }
+
+    /// Legal moves from `board` with the positions they lead to
+    fn children(&mut self, board: &Board) -> Vec<(Move, Board)> {
+        self.nodes += 1;
+        board
+            .list_all_valid_moves()
+            .into_iter()
+            .map(|mv| {
+                let mut child = *board;
+                child.do_move_from_coord(mv).unwrap();
+                (mv, child)
+            })
+            .collect()
+    }
+
+    /// Attacker moves from `board` with the positions they lead to, checks first as they're the likeliest
+    /// to mate. With one move left only checks can mate.
+    fn attacks(&mut self, board: &Board, moves: u32) -> Vec<(Move, Board)> {
+        let mut attacks = self.children(board);
+        attacks.retain(|(_, child)| child.get_check() || !(self.options.checks_only || moves == 1));
+        attacks.sort_by_key(|(_, child)| !child.get_check());
+        attacks
+    }
+
+    /// Whether the attacker to move on `board` mates in at most `moves`
+    fn mates(&mut self, board: &Board, moves: u32) -> bool {
+        if moves == 0 {
+            return false;
+        }
+        let key = (board.zobrist_key(), moves);
+        if let Some(&proven) = self.proven.get(&key) {
+            return proven;
+        }
+        let attacks = self.attacks(board, moves);
+        let mates = attacks.into_iter().any(|(_, child)| self.mates_after(&child, moves));
+        self.proven.insert(key, mates);
+        mates
+    }
+
+    /// Whether the attacker, having just moved to reach `board`, mates in at most `moves` counting that move
+    fn mates_after(&mut self, board: &Board, moves: u32) -> bool {
+        if board.get_checkmate() {
+            return true;
+        }
+        if moves == 1 || board.get_stalemate() {
+            return false;
+        }
+        let defences = self.children(board);
+        defences.into_iter().all(|(_, child)| self.mates(&child, moves - 1))
+    }
+
+    /// Every attacker move from `board` that mates in at most `moves`, with the play after it
+    fn attack_tree(&mut self, board: &Board, moves: u32) -> Vec<AttackMove> {
+        if moves == 0 {
+            return Vec::new();
+        }
+        let attacks = self.attacks(board, moves);
+        let mut tree = Vec::new();
+        for (mv, child) in attacks {
+            if !self.mates_after(&child, moves) {
+                continue;
+            }
+            if child.get_checkmate() {
+                tree.push(AttackMove { mv, mate_in: 1, defences: Vec::new() });
+                continue;
+            }
+            let defences: Vec<Defence> = self
+                .children(&child)
+                .into_iter()
+                .map(|(mv, grandchild)| Defence { mv, continuations: self.attack_tree(&grandchild, moves - 1) })
+                .collect();
+            // The defender picks the reply that holds out longest, the attacker the quickest mate after it
+            let mate_in = 1 + defences
+                .iter()
+                .map(|defence| defence.continuations.iter().map(|attack| attack.mate_in).min().unwrap())
+                .max()
+                .unwrap();
+            tree.push(AttackMove { mv, mate_in, defences });
+        }
+        tree
+    }
+
+    /// Whether the side to move on `board` can be helped into mate with `plies` moves left, counting
+    /// both sides. The mating move is always the last one, so `plies` is even when the side to move is
+    /// the one to be mated.
+    fn helps(&mut self, board: &Board, plies: u32) -> bool {
+        let key = (board.zobrist_key(), plies);
+        if let Some(&proven) = self.proven.get(&key) {
+            return proven;
+        }
+        let children = self.children(board);
+        let helps = children.into_iter().any(|(_, child)| self.helps_after(&child, plies));
+        self.proven.insert(key, helps);
+        helps
+    }
+
+    /// Whether the move leading to `board` is part of a helpmate with `plies` left before it
+    fn helps_after(&mut self, board: &Board, plies: u32) -> bool {
+        if plies == 1 {
+            return board.get_checkmate();
+        }
+        // A mate or stalemate before the last move ends the game too early
+        !board.get_checkmate() && !board.get_stalemate() && self.helps(board, plies - 1)
+    }
+
+    /// Every helpmate from `board` with `plies` left
+    fn help_lines(&mut self, board: &Board, plies: u32) -> Vec<Vec<Move>> {
+        let mut lines = Vec::new();
+        for (mv, child) in self.children(board) {
+            if !self.helps_after(&child, plies) {
+                continue;
+            }
+            if plies == 1 {
+                lines.push(vec![mv]);
+                continue;
+            }
+            for rest in self.help_lines(&child, plies - 1) {
+                let mut line = Vec::with_capacity(plies as usize);
+                line.push(mv);
+                line.extend(rest);
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    /// Whether the side to move on `board` can force the opponent to mate it within `moves`
+    fn forces_selfmate(&mut self, board: &Board, moves: u32) -> bool {
+        if moves == 0 {
+            return false;
+        }
+        let key = (board.zobrist_key(), moves);
+        if let Some(&proven) = self.proven.get(&key) {
+            return proven;
+        }
+        let children = self.children(board);
+        let forces = children.into_iter().any(|(_, child)| self.selfmate_after(&child, moves));
+        self.proven.insert(key, forces);
+        forces
+    }
+
+    /// Whether, with the opponent to move on `board`, every reply mates or leaves a selfmate in one move fewer
+    fn selfmate_after(&mut self, board: &Board, moves: u32) -> bool {
+        if board.get_checkmate() || board.get_stalemate() {
+            return false;
+        }
+        let replies = self.children(board);
+        replies
+            .into_iter()
+            .all(|(_, child)| child.get_checkmate() || (moves > 1 && self.forces_selfmate(&child, moves - 1)))
+    }
+
+    /// Every move from `board` that forces a selfmate within `moves`, with the play after it
+    fn selfmate_tree(&mut self, board: &Board, moves: u32) -> Vec<AttackMove> {
+        if moves == 0 {
+            return Vec::new();
+        }
+        let mut tree = Vec::new();
+        for (mv, child) in self.children(board) {
+            if !self.selfmate_after(&child, moves) {
+                continue;
+            }
+            let defences: Vec<Defence> = self
+                .children(&child)
+                .into_iter()
+                .map(|(mv, grandchild)| {
+                    let continuations = if grandchild.get_checkmate() { Vec::new() } else { self.selfmate_tree(&grandchild, moves - 1) };
+                    Defence { mv, continuations }
+                })
+                .collect();
+            // Replies that mate right away end the problem with this move
+            let mate_in = 1 + defences
+                .iter()
+                .map(|defence| defence.continuations.iter().map(|attack| attack.mate_in).min().unwrap_or(0))
+                .max()
+                .unwrap();
+            tree.push(AttackMove { mv, mate_in, defences });
+        }
+        tree
+    }
+}
@@ -0,0 +1,1140 @@
+use crate::board::{
+    Board,
+    Coord,
+    Square,
+};
+use crate::engine::is_capture;
+use crate::pieces::{
+    PieceColor,
+    PieceKind,
+};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{fmt, fs, io};
+
+#[derive(Debug)]
+pub enum SyzygyError {
+    Io(io::Error),
+    /// A file in the tablebase directory isn't a Syzygy table
+    Format(String),
+}
+pub type Result<T> = std::result::Result<T, SyzygyError>;
+
+impl std::error::Error for SyzygyError {}
+
+impl fmt::Display for SyzygyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyzygyError::Io(err) => write!(f, "Could not read tablebases: {err}"),
+            SyzygyError::Format(desc) => write!(f, "Invalid tablebase: {desc}"),
+        }
+    }
+}
+
+impl From<io::Error> for SyzygyError {
+    fn from(err: io::Error) -> Self {
+        SyzygyError::Io(err)
+    }
+}
+
+/// Game theoretical result for the side to move. Cursed wins and blessed losses are wins and losses
+/// that the fifty-move rule turns into draws.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    pub fn signum(self) -> i32 {
+        (self as i32).signum()
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32)).unwrap()
+    }
+}
+
+/// Endgame tablebases the search can consult. Positions with castling rights are never covered.
+pub trait Tablebase: Send + Sync {
+    /// Most pieces, kings included, of the positions covered
+    fn max_pieces(&self) -> usize;
+
+    /// Result with best play for the side to move, not counting moves already played towards the
+    /// fifty-move rule. `None` when the position isn't covered.
+    fn probe_wdl(&self, board: &Board) -> Option<Wdl>;
+
+    /// Plies until the next capture or pawn move with best play (DTZ), positive when the side to move
+    /// wins and negative when it loses. Cursed wins and blessed losses are 100 further from zero,
+    /// draws are 0 and a side that is mated gets -1. `None` when the position isn't covered.
+    fn probe_dtz(&self, board: &Board) -> Option<i32>;
+}
+
+/// Number of pieces on the board, kings included
+pub fn piece_count(board: &Board) -> usize {
+    board.board.iter().flatten().filter(|square| matches!(square, Square::Occupied(_))).count()
+}
+
+/// Whether castling is still possible for either side. Rights whose king or rook has been captured
+/// don't count, the board only drops them when those pieces move.
+pub fn has_castling_rights(board: &Board) -> bool {
+    let is = |x, y, kind, color| {
+        board.piece_at(Coord { x, y }).is_some_and(|piece| piece.kind == kind && piece.color == color)
+    };
+    let side = |(king_side, queen_side): (bool, bool), y, color| {
+        is(4, y, PieceKind::King, color)
+            && ((king_side && is(7, y, PieceKind::Rook, color)) || (queen_side && is(0, y, PieceKind::Rook, color)))
+    };
+    side(board.white_castle, 7, PieceColor::White) || side(board.black_castle, 0, PieceColor::Black)
+}
+
+// Squares follow the tablebase convention from here on: a1 = 0, b1 = 1, ..., h8 = 63
+
+#[inline]
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+
+#[inline]
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+
+/// Distance of the square above (positive) or below (negative) the a1-h8 diagonal
+#[inline]
+fn off_a1h8(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+/// Piece codes used in table files: 1 (pawn) to 6 (king) for White, 8 more for Black
+#[inline]
+fn piece_code(color: PieceColor, kind: PieceKind) -> u8 {
+    kind as u8 + 1 + 8 * (color == PieceColor::Black) as u8
+}
+
+/// Lookup tables behind the position indexing
+struct IndexTables {
+    /// Squares below the a1-h8 diagonal to 0..28
+    map_b1h1h7: [usize; 64],
+    /// Squares of the a1-d1-d4 triangle to 0..10, the diagonal last
+    map_a1d1d4: [usize; 64],
+    /// The 462 legal placements of two kings with the first in the a1-d1-d4 triangle
+    map_kk: [[usize; 64]; 10],
+    /// `binomial[k][n]` ways to choose k of n squares
+    binomial: [[u64; 64]; 7],
+    /// Squares a2-h7 to 0..48, higher towards the edge and the first rank. The pawn with the highest
+    /// value leads.
+    map_pawns: [usize; 64],
+    /// Index of the leading pawn group by number of leading pawns and square of the leader
+    lead_pawn_idx: [[u64; 64]; 6],
+    /// Number of leading pawn placements by number of leading pawns and file of the leader
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl IndexTables {
+    fn new() -> Self {
+        let mut tables = IndexTables {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 7],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                tables.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..28 {
+            if off_a1h8(sq) < 0 && file_of(sq) <= 3 {
+                tables.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && file_of(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            tables.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        let adjacent = |a: usize, b: usize| file_of(a).abs_diff(file_of(b)) <= 1 && rank_of(a).abs_diff(rank_of(b)) <= 1;
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                // Squares outside the triangle are 0 too, b1 is the real 0
+                if tables.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    if adjacent(s1, s2) || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        tables.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            tables.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        tables.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                tables.binomial[k][n] =
+                    if k > 0 { tables.binomial[k - 1][n - 1] } else { 0 } + if k < n { tables.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available = 47;
+        for lead_count in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_count == 1 {
+                        tables.map_pawns[sq] = available;
+                        tables.map_pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    tables.lead_pawn_idx[lead_count][sq] = idx;
+                    idx += tables.binomial[lead_count - 1][tables.map_pawns[sq]];
+                }
+                tables.lead_pawns_size[lead_count][file] = idx;
+            }
+        }
+        tables
+    }
+}
+
+lazy_static! {
+    static ref INDEX: IndexTables = IndexTables::new();
+}
+
+/// The pieces of a table, side 0 being the one written first in its name
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Material {
+    /// Indexed by side and `PieceKind as usize`
+    counts: [[usize; 6]; 2],
+}
+
+const PIECE_LETTERS: [(char, PieceKind); 6] = [
+    ('K', PieceKind::King),
+    ('Q', PieceKind::Queen),
+    ('R', PieceKind::Rook),
+    ('B', PieceKind::Bishop),
+    ('N', PieceKind::Knight),
+    ('P', PieceKind::Pawn),
+];
+
+impl Material {
+    /// Reads a table name such as `KRPvKR`
+    fn parse(name: &str) -> Option<Self> {
+        let (first, second) = name.split_once('v')?;
+        let mut counts = [[0; 6]; 2];
+        for (side, pieces) in [first, second].into_iter().enumerate() {
+            for c in pieces.chars() {
+                let (_, kind) = PIECE_LETTERS.iter().find(|(letter, _)| *letter == c)?;
+                counts[side][*kind as usize] += 1;
+            }
+            if counts[side][PieceKind::King as usize] != 1 {
+                return None;
+            }
+        }
+        let material = Material { counts };
+        (material.piece_count() <= MAX_PIECES).then_some(material)
+    }
+
+    fn of(board: &Board) -> Self {
+        let mut counts = [[0; 6]; 2];
+        for piece in board.board.iter().flatten().filter_map(|square| match square {
+            Square::Occupied(piece) => Some(piece),
+            Square::Empty => None,
+        }) {
+            counts[piece.color as usize][piece.kind as usize] += 1;
+        }
+        Material { counts }
+    }
+
+    fn flipped(&self) -> Self {
+        Material { counts: [self.counts[1], self.counts[0]] }
+    }
+
+    /// Canonical name, pieces ordered from king to pawn on each side
+    fn name(&self) -> String {
+        let side = |counts: &[usize; 6]| -> String {
+            PIECE_LETTERS.iter().flat_map(|(letter, kind)| std::iter::repeat_n(*letter, counts[*kind as usize])).collect()
+        };
+        format!("{}v{}", side(&self.counts[0]), side(&self.counts[1]))
+    }
+
+    fn piece_count(&self) -> usize {
+        self.counts.iter().flatten().sum()
+    }
+
+    fn pawns(&self, side: usize) -> usize {
+        self.counts[side][PieceKind::Pawn as usize]
+    }
+}
+
+/// Table flags of [`PairsData`]
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Most pieces a Syzygy table can hold
+pub const MAX_PIECES: usize = 7;
+
+/// One compressed sub-table: a side to move and, with pawns, a file of the leading pawn. Values are
+/// Huffman coded symbols that expand to runs of values through a pairing tree. Offsets point into
+/// the table file.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    /// A sparse index entry for every `span` values
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: usize,
+    /// Lowest symbol of each code length (u16s)
+    lowest_sym: usize,
+    /// Left and right child of each symbol (12 bits each, 3 bytes per symbol)
+    btree: usize,
+    /// Number of values minus one in each block (u16s)
+    block_length: usize,
+    block_length_size: usize,
+    /// Block and offset in the block for every `span` values (u32 + u16)
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// Lowest code of each length, left aligned in 64 bits
+    base64: Vec<u64>,
+    /// Number of values minus one each symbol expands to
+    symlen: Vec<u8>,
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    /// Offsets of the DTZ value maps for win, loss, cursed win and blessed loss
+    map_idx: [usize; 4],
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+impl TableKind {
+    fn magic(self) -> [u8; 4] {
+        match self {
+            TableKind::Wdl => [0x71, 0xE8, 0x23, 0x5D],
+            TableKind::Dtz => [0xD7, 0x66, 0x0C, 0xA5],
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+}
+
+/// Bounds checked little and big endian reads from a table file
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.0
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(|| SyzygyError::Format("File is truncated".to_string()))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+}
+
+/// What a table says about a position
+enum TableProbe {
+    Value(i32),
+    /// DTZ tables only store one side to move, and this isn't it
+    ChangeStm,
+}
+
+/// A table file read into memory
+struct Table {
+    kind: TableKind,
+    bytes: Vec<u8>,
+    /// Both sides have the same pieces, only positions with White to move are stored
+    symmetric: bool,
+    has_pawns: bool,
+    /// Pawns of the leading side and of the other one
+    pawn_count: [usize; 2],
+    piece_count: usize,
+    /// Some side has exactly one piece of some kind besides the king
+    has_unique_pieces: bool,
+    /// Indexed by side to move (WDL only) and file of the leading pawn
+    items: [[PairsData; 4]; 2],
+    /// Start of the DTZ value maps
+    map: usize,
+}
+
+impl Table {
+    fn parse(kind: TableKind, material: &Material, bytes: Vec<u8>) -> Result<Self> {
+        if bytes.get(..4) != Some(&kind.magic()[..]) {
+            return Err(SyzygyError::Format(format!("Not a {} file", kind.extension())));
+        }
+        let has_pawns = material.pawns(0) + material.pawns(1) > 0;
+        // The side with fewer pawns leads, as it compresses better
+        let first_leads = material.pawns(1) == 0 || (material.pawns(0) > 0 && material.pawns(1) >= material.pawns(0));
+        let pawn_count = if first_leads {
+            [material.pawns(0), material.pawns(1)]
+        } else {
+            [material.pawns(1), material.pawns(0)]
+        };
+        let has_unique_pieces = (0..2).any(|side| material.counts[side][..PieceKind::King as usize].contains(&1));
+        let mut table = Table {
+            kind,
+            bytes: Vec::new(),
+            symmetric: material.flipped() == *material,
+            has_pawns,
+            pawn_count,
+            piece_count: material.piece_count(),
+            has_unique_pieces,
+            items: Default::default(),
+            map: 0,
+        };
+        table.read_layout(&Reader(&bytes))?;
+        table.bytes = bytes;
+        Ok(table)
+    }
+
+    fn sides(&self) -> usize {
+        match self.kind {
+            TableKind::Wdl if !self.symmetric => 2,
+            _ => 1,
+        }
+    }
+
+    fn files(&self) -> usize {
+        if self.has_pawns { 4 } else { 1 }
+    }
+
+    fn read_layout(&mut self, r: &Reader) -> Result<()> {
+        const SPLIT: u8 = 1;
+        const HAS_PAWNS: u8 = 2;
+        let flags = r.u8(4)?;
+        if (flags & HAS_PAWNS != 0) != self.has_pawns || (flags & SPLIT != 0) == self.symmetric {
+            return Err(SyzygyError::Format("Header doesn't match the table name".to_string()));
+        }
+        let mut p = 5;
+        let (sides, files) = (self.sides(), self.files());
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+
+        for f in 0..files {
+            let order_byte = r.u8(p)?;
+            let pawn_order_byte = if pp { r.u8(p + 1)? } else { 0xFF };
+            let order = [[order_byte & 0xF, pawn_order_byte & 0xF], [order_byte >> 4, pawn_order_byte >> 4]];
+            p += 1 + pp as usize;
+            for k in 0..self.piece_count {
+                let byte = r.u8(p)?;
+                for i in 0..sides {
+                    self.items[i][f].pieces[k] = if i == 0 { byte & 0xF } else { byte >> 4 };
+                }
+                p += 1;
+            }
+            for (i, order) in order.iter().enumerate().take(sides) {
+                self.set_groups(i, f, *order)?;
+            }
+        }
+        p += p & 1;
+
+        for f in 0..files {
+            for i in 0..sides {
+                p = self.items[i][f].read_sizes(r, p)?;
+            }
+        }
+        if self.kind == TableKind::Dtz {
+            p = self.read_dtz_maps(r, p)?;
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut self.items[i][f];
+                d.sparse_index = p;
+                p += d.sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut self.items[i][f];
+                d.block_length = p;
+                p += d.block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut self.items[i][f];
+                // Blocks are 64 byte aligned
+                p = (p + 0x3F) & !0x3F;
+                d.data = p;
+                p += d.num_blocks * d.block_size;
+                if d.num_blocks > 0 {
+                    r.bytes(d.sparse_index, d.sparse_index_size * 6)?;
+                    r.bytes(d.block_length, d.block_length_size * 2)?;
+                    r.bytes(d.data, d.num_blocks * d.block_size)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the pieces into groups encoded together and works out the index multiplier of each
+    fn set_groups(&mut self, side: usize, file: usize, order: [u8; 2]) -> Result<()> {
+        let (has_pawns, has_unique_pieces, piece_count, pawn_count) =
+            (self.has_pawns, self.has_unique_pieces, self.piece_count, self.pawn_count);
+        let d = &mut self.items[side][file];
+        let mut n = 0;
+        let mut first_len: i32 = if has_pawns { 0 } else if has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        // Groups are combined as g1 * N(g2) * N(g3) + g2 * N(g3) + g3 in the order the file gives
+        let pp = has_pawns && pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {
+                    INDEX.lead_pawns_size[d.group_len[0].min(5)][file]
+                } else if has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                d.group_idx[1] = idx;
+                idx *= INDEX.binomial[d.group_len[1].min(6)][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= INDEX.binomial[d.group_len[next].min(6)][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+            if k > MAX_PIECES {
+                return Err(SyzygyError::Format("Invalid group order".to_string()));
+            }
+        }
+        d.group_idx[n] = idx;
+        Ok(())
+    }
+
+    fn read_dtz_maps(&mut self, r: &Reader, mut p: usize) -> Result<usize> {
+        self.map = p;
+        for f in 0..self.files() {
+            let d = &mut self.items[0][f];
+            if d.flags & FLAG_MAPPED == 0 {
+                continue;
+            }
+            if d.flags & FLAG_WIDE != 0 {
+                p += p & 1;
+                for i in 0..4 {
+                    d.map_idx[i] = (p - self.map) / 2 + 1;
+                    p += 2 * r.u16(p)? as usize + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    d.map_idx[i] = p - self.map + 1;
+                    p += r.u8(p)? as usize + 1;
+                }
+            }
+        }
+        Ok(p + (p & 1))
+    }
+
+    /// Index of the position in the sub-table and the value stored there
+    fn probe(&self, board: &Board, black_stronger: bool, wdl: Wdl) -> Option<TableProbe> {
+        let tables = &*INDEX;
+        // Tables are stored with the side written first as White. With the same pieces on both sides
+        // only White to move is stored, so Black to move is looked up with colors swapped.
+        let flip = black_stronger || (self.symmetric && board.turn == PieceColor::Black);
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ (board.turn == PieceColor::Black) as usize;
+
+        let mut on_board: Vec<(usize, u8)> = Vec::with_capacity(MAX_PIECES);
+        for y in (0..8).rev() {
+            for x in 0..8 {
+                if let Square::Occupied(piece) = board.board[y][x] {
+                    on_board.push(((7 - y) * 8 + x, piece_code(piece.color, piece.kind)));
+                }
+            }
+        }
+        if on_board.len() != self.piece_count {
+            return None;
+        }
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_count = 0;
+        let mut lead_piece = 0;
+        let mut file = 0;
+        if self.has_pawns {
+            // Pawns come first in every sub-table, in the leading color
+            lead_piece = self.items[0][0].pieces[0] ^ flip_color;
+            for &(sq, piece) in &on_board {
+                if piece == lead_piece {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_count = size;
+            let leader = (0..lead_count).fold(0, |best, i| {
+                if tables.map_pawns[squares[i]] > tables.map_pawns[squares[best]] { i } else { best }
+            });
+            squares.swap(0, leader);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        if self.kind == TableKind::Dtz {
+            // Symmetric tables without pawns are the same for both sides to move
+            let stored = (self.items[0][file].flags & FLAG_STM) as usize;
+            let both_stored = self.symmetric && !self.has_pawns;
+            if stored != stm && !both_stored {
+                return Some(TableProbe::ChangeStm);
+            }
+        }
+
+        for &(sq, piece) in &on_board {
+            if self.has_pawns && piece == lead_piece {
+                continue;
+            }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = piece ^ flip_color;
+            size += 1;
+        }
+
+        let d = &self.items[if self.kind == TableKind::Wdl { stm } else { 0 }][file];
+        // Same piece order as the table
+        for i in lead_count..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| d.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // Mirror so the leading piece is on files a-d
+        if file_of(squares[0]) > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = tables.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&sq| tables.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += tables.binomial[i][tables.map_pawns[sq]];
+            }
+        } else {
+            // Without pawns the board can also be mirrored vertically and along the diagonal
+            if rank_of(squares[0]) > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                match off_a1h8(squares[i]) {
+                    0 => continue,
+                    off if off > 0 => {
+                        for sq in &mut squares[i..size] {
+                            *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                        }
+                    }
+                    _ => {}
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                idx = if off_a1h8(s0) != 0 {
+                    ((tables.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2) as u64
+                } else if off_a1h8(s1) != 0 {
+                    ((6 * 63 + rank_of(s0) * 28 + tables.map_b1h1h7[s1]) * 62 + s2 - adjust2) as u64
+                } else if off_a1h8(s2) != 0 {
+                    (6 * 63 * 62 + 4 * 28 * 62 + rank_of(s0) * 7 * 28 + (rank_of(s1) - adjust1) * 28 + tables.map_b1h1h7[s2]) as u64
+                } else {
+                    (6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(s0) * 7 * 6 + (rank_of(s1) - adjust1) * 6 + (rank_of(s2) - adjust2))
+                        as u64
+                };
+            } else {
+                idx = tables.map_kk[tables.map_a1d1d4[squares[0]]][squares[1]] as u64;
+            }
+        }
+
+        // The remaining groups, each as a combination of the squares left over by the earlier ones
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&s| sq > s).count();
+                n += tables.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&self.bytes, idx)? as i32;
+        Some(TableProbe::Value(match self.kind {
+            TableKind::Wdl => value - 2,
+            TableKind::Dtz => self.map_dtz(d, value, wdl)?,
+        }))
+    }
+
+    /// Turns a stored DTZ value into plies
+    fn map_dtz(&self, d: &PairsData, mut value: i32, wdl: Wdl) -> Option<i32> {
+        let r = Reader(&self.bytes);
+        if d.flags & FLAG_MAPPED != 0 {
+            let map = d.map_idx[[1, 3, 0, 2, 0][(wdl as i32 + 2) as usize]];
+            value = if d.flags & FLAG_WIDE != 0 {
+                r.u16(self.map + 2 * (map + value as usize)).ok()? as i32
+            } else {
+                r.u8(self.map + map + value as usize).ok()? as i32
+            };
+        }
+        // Values are stored in full moves unless the table says otherwise
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+impl PairsData {
+    fn read_sizes(&mut self, r: &Reader, mut p: usize) -> Result<usize> {
+        self.flags = r.u8(p)?;
+        p += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // The one value every position has
+            self.min_sym_len = r.u8(p)? as usize;
+            return Ok(p + 1);
+        }
+
+        // The group lengths end with a 0, whose multiplier is the table size
+        let groups = self.group_len.iter().position(|&len| len == 0).unwrap_or(MAX_PIECES);
+        let size = self.group_idx[groups];
+        self.block_size = 1 << r.u8(p)?;
+        self.span = 1 << r.u8(p + 1)?;
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = r.u8(p + 2)? as usize;
+        self.num_blocks = r.u32(p + 3)? as usize;
+        // Padded so the sparse index never points past the end
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = r.u8(p + 7)? as usize;
+        self.min_sym_len = r.u8(p + 8)? as usize;
+        p += 9;
+        if max_sym_len < self.min_sym_len || max_sym_len > 64 || self.min_sym_len == 0 {
+            return Err(SyzygyError::Format("Invalid symbol lengths".to_string()));
+        }
+        self.lowest_sym = p;
+
+        // Canonical code where longer codes have lower values, so base64 decreases with the length
+        let lengths = max_sym_len - self.min_sym_len + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = r.u16(self.lowest_sym + 2 * i)? as u64;
+            let next_lowest = r.u16(self.lowest_sym + 2 * (i + 1))? as u64;
+            self.base64[i] = (self.base64[i + 1] + lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl((64 - i - self.min_sym_len) as u32).unwrap_or(0);
+        }
+        p += lengths * 2;
+
+        let symbols = r.u16(p)? as usize;
+        p += 2;
+        self.btree = p;
+        r.bytes(self.btree, symbols * 3)?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.symbol_length(r.0, sym, &mut visited)?;
+            }
+        }
+        Ok(p + symbols * 3 + (symbols & 1))
+    }
+
+    /// Left and right child of `sym` in the pairing tree
+    #[inline]
+    fn children(&self, bytes: &[u8], sym: usize) -> Option<(usize, usize)> {
+        let lr = bytes.get(self.btree + 3 * sym..self.btree + 3 * sym + 3)?;
+        let left = ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+        let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+        Some((left, right))
+    }
+
+    /// Number of values minus one that `sym` expands to
+    fn symbol_length(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Result<u8> {
+        let invalid = || SyzygyError::Format("Invalid symbol tree".to_string());
+        // The tree is acyclic so marking before recursing is fine
+        visited[sym] = true;
+        let (left, right) = self.children(bytes, sym).ok_or_else(invalid)?;
+        if right == 0xFFF {
+            return Ok(0);
+        }
+        for child in [left, right] {
+            if !*visited.get(child).ok_or_else(invalid)? {
+                self.symlen[child] = self.symbol_length(bytes, child, visited)?;
+            }
+        }
+        Ok(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1))
+    }
+
+    /// Value number `idx` of the sub-table, `None` if the data doesn't decode
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Option<u16> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as u16);
+        }
+        let r = Reader(bytes);
+        let k = (idx / self.span) as usize;
+        let mut block = r.u32(self.sparse_index + 6 * k).ok()? as usize;
+        let mut offset = r.u16(self.sparse_index + 6 * k + 4).ok()? as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |block: usize| -> Option<i64> {
+            if block >= self.block_length_size {
+                return None;
+            }
+            Some(r.u16(self.block_length + 2 * block).ok()? as i64)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+        if block >= self.num_blocks {
+            return None;
+        }
+
+        let mut ptr = self.data + block * self.block_size;
+        let read_be = |ptr: usize, len: usize| -> Option<u64> {
+            Some(bytes.get(ptr..ptr + len)?.iter().fold(0u64, |acc, &b| acc << 8 | b as u64))
+        };
+        let mut buf64 = read_be(ptr, 8)?;
+        ptr += 8;
+        let mut buf64_size = 64;
+        let lowest_sym = |len: usize| r.u16(self.lowest_sym + 2 * len).ok().map(|sym| sym as usize);
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < *self.base64.get(len)? {
+                len += 1;
+            }
+            sym = ((buf64 - self.base64[len]).checked_shr((64 - len - self.min_sym_len) as u32).unwrap_or(0)) as usize;
+            sym += lowest_sym(len)?;
+            let run = *self.symlen.get(sym)? as i64 + 1;
+            if offset < run {
+                break;
+            }
+            offset -= run;
+            len += self.min_sym_len;
+            buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= read_be(ptr, 4)? << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        // Walk down the pairing tree to the value at the offset
+        while *self.symlen.get(sym)? != 0 {
+            let (left, right) = self.children(bytes, sym)?;
+            let run = *self.symlen.get(left)? as i64 + 1;
+            if offset < run {
+                sym = left;
+            } else {
+                offset -= run;
+                sym = right;
+            }
+        }
+        Some(self.children(bytes, sym)?.0 as u16)
+    }
+}
+
+/// A table file found in the directory, read on first use
+struct TableFile {
+    path: PathBuf,
+    material: Material,
+    table: OnceLock<Option<Table>>,
+}
+
+impl TableFile {
+    fn get(&self, kind: TableKind) -> Option<&Table> {
+        self.table
+            .get_or_init(|| fs::read(&self.path).ok().and_then(|bytes| Table::parse(kind, &self.material, bytes).ok()))
+            .as_ref()
+    }
+}
+
+/// DTZ of a position whose best move is a capture or pawn move with the given result
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/// Syzygy WDL (`.rtbw`) and DTZ (`.rtbz`) tables from a directory. Files are only read when a
+/// position needs them, and files that turn out to be broken count as missing.
+///
+/// Like the board, probing only promotes to a queen, so the DTZ of the rare wins that need an
+/// underpromotion is wrong.
+pub struct SyzygyTablebase {
+    wdl: HashMap<String, TableFile>,
+    dtz: HashMap<String, TableFile>,
+    max_pieces: usize,
+}
+
+impl SyzygyTablebase {
+    /// Finds the tables in `dir`. Files that aren't named like tables are ignored, but a table name
+    /// with the wrong header is an error.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let mut tablebase = SyzygyTablebase { wdl: HashMap::new(), dtz: HashMap::new(), max_pieces: 0 };
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let (Some(stem), Some(extension)) = (path.file_stem().and_then(|s| s.to_str()), path.extension()) else {
+                continue;
+            };
+            let kind = match extension.to_str() {
+                Some("rtbw") => TableKind::Wdl,
+                Some("rtbz") => TableKind::Dtz,
+                _ => continue,
+            };
+            let Some(material) = Material::parse(stem) else {
+                continue;
+            };
+            let mut magic = [0; 4];
+            io::Read::read_exact(&mut fs::File::open(&path)?, &mut magic)?;
+            if magic != kind.magic() {
+                return Err(SyzygyError::Format(format!("{} has the wrong header", path.display())));
+            }
+            tablebase.max_pieces = tablebase.max_pieces.max(material.piece_count());
+            let tables = match kind {
+                TableKind::Wdl => &mut tablebase.wdl,
+                TableKind::Dtz => &mut tablebase.dtz,
+            };
+            tables.insert(material.name(), TableFile { path, material, table: OnceLock::new() });
+        }
+        Ok(tablebase)
+    }
+
+    /// Number of WDL and DTZ tables found
+    pub fn tables(&self) -> (usize, usize) {
+        (self.wdl.len(), self.dtz.len())
+    }
+
+    fn covers(&self, board: &Board) -> bool {
+        piece_count(board) <= self.max_pieces.max(2) && !has_castling_rights(board)
+    }
+
+    /// Looks the position up in a single table. The result may be wrong when the best move is a
+    /// capture (or a pawn move for DTZ), see [`SyzygyTablebase::search`].
+    fn probe_table(&self, board: &Board, kind: TableKind, wdl: Wdl) -> Option<TableProbe> {
+        let material = Material::of(board);
+        if material.piece_count() == 2 {
+            return Some(TableProbe::Value(0));
+        }
+        let tables = match kind {
+            TableKind::Wdl => &self.wdl,
+            TableKind::Dtz => &self.dtz,
+        };
+        let (file, black_stronger) = match tables.get(&material.name()) {
+            Some(file) => (file, false),
+            None => (tables.get(&material.flipped().name())?, true),
+        };
+        file.get(kind)?.probe(board, black_stronger, wdl)
+    }
+
+    /// WDL of `board` and whether its best move is a capture, or a pawn move when `with_pawn_moves`.
+    /// Tables store "don't care" values for positions where such a move wins, and know nothing about
+    /// en passant, so those moves are always tried first.
+    fn search(&self, board: &Board, with_pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = board.list_all_valid_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for &mv in &moves {
+            let pawn_move = board.piece_at(mv.from).is_some_and(|piece| piece.kind == PieceKind::Pawn);
+            let zeroing = is_capture(board, mv) || (with_pawn_moves && pawn_move);
+            if !zeroing {
+                continue;
+            }
+            searched += 1;
+            let mut child = *board;
+            child.do_move_from_coord(mv).ok()?;
+            let value = -self.search(&child, false)?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // When every move was tried the table isn't needed, and could be wrong
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            match self.probe_table(board, TableKind::Wdl, Wdl::Draw)? {
+                TableProbe::Value(value) => Wdl::from_value(value)?,
+                TableProbe::ChangeStm => return None,
+            }
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || all_searched));
+        }
+        Some((value, false))
+    }
+}
+
+impl Tablebase for SyzygyTablebase {
+    fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_wdl(&self, board: &Board) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+        let (wdl, zeroing) = self.search(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        // The stored value is meaningless when the best move zeroes the counter
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(board, TableKind::Dtz, wdl)? {
+            TableProbe::Value(dtz) => {
+                let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+                Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum())
+            }
+            TableProbe::ChangeStm => {
+                // Only the other side to move is stored, so take the best reply one ply down
+                let mut best = None;
+                for mv in board.list_all_valid_moves() {
+                    let zeroing = is_capture(board, mv) || board.piece_at(mv.from).is_some_and(|piece| piece.kind == PieceKind::Pawn);
+                    let mut child = *board;
+                    child.do_move_from_coord(mv).ok()?;
+                    let dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&child, false)?.0)
+                    } else {
+                        let dtz = -self.probe_dtz(&child)?;
+                        if dtz == 1 && child.get_checkmate() {
+                            best = Some(1);
+                        }
+                        dtz + dtz.signum()
+                    };
+                    if dtz.signum() == wdl.signum() && best.is_none_or(|best| dtz < best) {
+                        best = Some(dtz);
+                    }
+                }
+                Some(best.unwrap_or(-1))
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 extern crate test;
 
 #[cfg(test)]
+#[allow(clippy::module_inception, clippy::bool_comparison)]
 mod tests {
     use super::*;
     use test::Bencher;
@@ -15,7 +16,102 @@ mod tests {
         PieceColor,
         PieceKind,
     };
-    use crate::engine::{make_best_move};
+    use crate::engine::{
+        make_best_move,
+        search_best_move,
+        search_with_options,
+        search,
+        search_with_evaluator,
+        SearchOptions,
+        SearchLimits,
+        SearchPool,
+        StopSignal,
+        Engine,
+        EngineError,
+        mate_in,
+        mated_in,
+        score_to_tt,
+        score_from_tt,
+        Score,
+        is_mate_score,
+        DRAW,
+        TB_WIN,
+        TB_BOUND,
+    };
+    use crate::syzygy::{
+        SyzygyError,
+        SyzygyTablebase,
+        Tablebase,
+        Wdl,
+    };
+    use crate::solver::{
+        solve_helpmate,
+        solve_mate,
+        solve_selfmate,
+        SolverOptions,
+    };
+    use crate::skill::{
+        Skill,
+        MAX_LEVEL,
+    };
+    use crate::retrograde::{
+        Dtm,
+        RetrogradeError,
+        RetrogradeTablebase,
+    };
+    use crate::evaluation::{
+        evaluate,
+        evaluate_with,
+        game_phase,
+        piece_value,
+        Evaluator,
+        PstEvaluator,
+        PHASE_MAX,
+    };
+    use crate::activity::piece_activity;
+    use crate::params::{
+        EvalParams,
+        ParamsError,
+    };
+    use crate::tuner::{
+        Tuner,
+        TunerOptions,
+        TuningPosition,
+    };
+    use crate::nnue::{
+        Network,
+        NnueError,
+        NnueEvaluator,
+        FEATURES,
+    };
+    use crate::datagen::{
+        read_records,
+        GameResult,
+        PackedPosition,
+        SelfPlay,
+        SelfPlayOptions,
+        TrainingRecord,
+    };
+    use crate::book::{
+        decode_move,
+        encode_move,
+        polyglot_key,
+        Book,
+        BookBuilder,
+        BookBuilderOptions,
+        BookEntry,
+        BookSelection,
+        BookWeighting,
+    };
+    use crate::pgn::{
+        parse_san,
+        read_games,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::pawns::{
+        PawnHashTable,
+        PawnStructure,
+    };
 
 
     #[bench]
@@ -537,6 +633,22 @@ mod tests {
 
     }
 
+    #[test]
+    fn king_two_files_away_is_not_check() {
+        for fen in ["8/8/8/8/8/8/8/2k1K3 w - - 0 1", "8/8/8/8/8/8/8/2k1K3 b - - 0 1", "4k1K1/8/8/8/8/8/8/8 b - - 0 1", "8/8/8/8/4k1K1/8/8/8 w - - 0 1"] {
+            assert!(Board::from_fen(fen).unwrap().get_check() == false, "{fen}");
+        }
+        // Moving next to the castling squares of a king on its starting square
+        let mut board = Board::from_fen("8/8/8/8/8/8/8/1k2K3 b - - 0 1").unwrap();
+        board.do_move("b1", "c1").unwrap();
+        assert!(board.get_check() == false);
+        let mut board = Board::from_fen("4k2K/8/8/8/8/8/8/8 w - - 0 1").unwrap();
+        board.do_move("h8", "g8").unwrap();
+        assert!(board.get_check() == false);
+        let mut board = Board::from_fen("8/8/8/8/4k2K/8/8/8 w - - 0 1").unwrap();
+        board.do_move("h4", "g4").unwrap();
+    }
+
     #[test]
     fn engine() {
         let mut board = Board::default();
@@ -596,4 +708,1589 @@ mod tests {
         board.do_move_from_coord(Move::new(Coord{x: 5, y: 2}, Coord{x: 6, y: 1}, None)).unwrap();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn mate_score_reporting() {
+        assert_eq!(Score::from_search(mate_in(1)), Score::Mate(1));
+        assert_eq!(Score::from_search(mate_in(3)), Score::Mate(2));
+        assert_eq!(Score::from_search(mated_in(2)), Score::Mate(-1));
+        assert_eq!(Score::from_search(35), Score::Centipawns(35));
+        assert_eq!(Score::from_search(mate_in(5)).to_string(), "mate 3");
+    }
+
+    #[test]
+    fn mate_score_tt_roundtrip() {
+        // A mate found 3 plies below a node at ply 4 must read back as the same mate from ply 6
+        let score = mate_in(7);
+        let stored = score_to_tt(score, 4);
+        assert_eq!(stored, mate_in(3));
+        assert_eq!(score_from_tt(stored, 6), mate_in(9));
+        assert_eq!(score_from_tt(score_to_tt(mated_in(5), 2), 2), mated_in(5));
+        assert_eq!(score_from_tt(score_to_tt(120, 2), 9), 120);
+        // Tablebase wins and losses keep their distance the same way
+        assert_eq!(score_from_tt(score_to_tt(TB_WIN - 7, 4), 6), TB_WIN - 9);
+        assert_eq!(score_from_tt(score_to_tt(-TB_WIN + 5, 2), 8), -TB_WIN + 11);
+        assert_eq!(score_from_tt(score_to_tt(TB_BOUND - 1, 2), 9), TB_BOUND - 1);
+    }
+
+    #[test]
+    fn engine_reports_mate_in_one() {
+        let board = Board::from_fen("rnbqkb1r/1ppp1ppp/p4n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 0 1").unwrap();
+        let result = search_best_move(2, &board).unwrap();
+        assert_eq!(result.score(), Score::Mate(1));
+    }
+
+    #[test]
+    fn engine_reports_getting_mated() {
+        // Kg8 is forced and Rb8 mates
+        let board = Board::from_fen("7k/R7/1R6/8/8/8/8/K7 b - - 0 1").unwrap();
+        let result = search_best_move(2, &board).unwrap();
+        assert_eq!(result.score(), Score::Mate(-1));
+    }
+
+    #[test]
+    fn engine_no_moves_when_checkmated() {
+        let board = Board::from_fen("r1b1k1nr/ppp3pp/2np1p2/4p3/3bP3/3B1P2/PPPBNqPP/RN1QK2R w kq - 0 1").unwrap();
+        assert!(search_best_move(2, &board).is_none());
+    }
+
+    #[test]
+    fn null_move_passes_turn() {
+        let mut board = Board::default();
+        board.do_move("e2", "e4").unwrap();
+        assert!(board.en_passant.is_some());
+        board.make_null_move();
+        assert_eq!(board.turn, PieceColor::White);
+        assert!(board.en_passant.is_none());
+    }
+
+    #[test]
+    fn engine_mate_in_two_with_and_without_selectivity() {
+        let board = Board::from_fen("r3r1k1/5ppp/2Q5/2pP4/1P6/2P2N2/P2P1PPP/R3R1K1 w - - 0 1").unwrap();
+        let full_width = search_with_options(3, &board, SearchOptions::full_width()).unwrap();
+        let selective = search_with_options(3, &board, SearchOptions::default()).unwrap();
+        assert_eq!(full_width.score(), Score::Mate(2));
+        assert_eq!(selective.score(), Score::Mate(2));
+    }
+
+    #[test]
+    fn search_depth_limit() {
+        let board = Board::default();
+        let result = search(&board, &SearchLimits::to_depth(2), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(result.depth, 2);
+    }
+
+    #[test]
+    fn search_node_limit() {
+        let board = Board::default();
+        let limits = SearchLimits { nodes: Some(500), ..Default::default() };
+        let result = search(&board, &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert!(board.is_valid_move(result.best_move));
+        assert!(result.depth < 10);
+    }
+
+    #[test]
+    fn search_movetime_limit() {
+        let board = Board::default();
+        let start = std::time::Instant::now();
+        let limits = SearchLimits::for_time(std::time::Duration::from_millis(300));
+        let result = search(&board, &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(board.is_valid_move(result.best_move));
+    }
+
+    #[test]
+    fn search_stopped_from_another_thread() {
+        let board = Board::default();
+        let stop = StopSignal::new();
+        let stopper = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                stop.stop();
+            })
+        };
+        let result = search(&board, &SearchLimits::infinite(), SearchOptions::default(), &stop).unwrap();
+        stopper.join().unwrap();
+        assert!(board.is_valid_move(result.best_move));
+    }
+
+    #[test]
+    fn search_stopped_before_the_first_iteration() {
+        // A queen up, which a search stopped straight away must not report as getting mated
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let stop = StopSignal::new();
+        stop.stop();
+        let result = search(&board, &SearchLimits::infinite(), SearchOptions::default(), &stop).unwrap();
+        assert_eq!(result.depth, 0);
+        assert!(matches!(result.score(), Score::Centipawns(score) if score > 500), "{:?}", result.score());
+        assert_eq!(result.lines[0].score, result.score);
+    }
+
+    #[test]
+    fn search_mate_limit() {
+        let board = Board::from_fen("r3r1k1/5ppp/2Q5/2pP4/1P6/2P2N2/P2P1PPP/R3R1K1 w - - 0 1").unwrap();
+        let limits = SearchLimits { mate: Some(2), ..Default::default() };
+        let result = search(&board, &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(result.score(), Score::Mate(2));
+        assert!(result.depth <= 3);
+    }
+
+    #[test]
+    fn search_multi_pv() {
+        // Taking the queen is far ahead of anything else
+        let board = Board::from_fen("q3k3/8/8/8/8/8/6PP/R3K3 w - - 0 1").unwrap();
+        let options = SearchOptions { multi_pv: 3, ..Default::default() };
+        let result = search(&board, &SearchLimits::to_depth(3), options, &StopSignal::new()).unwrap();
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.best_move, Move::new(Coord::from_notation("a1").unwrap(), Coord::from_notation("a8").unwrap(), None));
+        assert_eq!(result.lines[0].moves[0], result.best_move);
+        assert_eq!(result.lines[0].score, result.score);
+        assert!(result.lines[0].moves.len() > 1);
+        assert!(result.lines[1].score < result.score - 500);
+        assert!(result.lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        for (i, line) in result.lines.iter().enumerate() {
+            assert!(result.lines[..i].iter().all(|other| other.moves[0] != line.moves[0]));
+            let mut position = board;
+            for &mv in &line.moves {
+                position.do_move_from_coord(mv).unwrap();
+            }
+        }
+
+        // Never more lines than legal moves, and one line by default
+        let board = Board::from_fen("7k/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let options = SearchOptions { multi_pv: 10, ..Default::default() };
+        let result = search(&board, &SearchLimits::to_depth(2), options, &StopSignal::new()).unwrap();
+        assert_eq!(result.lines.len(), 3);
+        let result = search(&board, &SearchLimits::to_depth(2), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(result.lines.len(), 1);
+    }
+
+    #[test]
+    fn engine_multi_pv_option() {
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "1").unwrap();
+        engine.set_option("MultiPV", "4").unwrap();
+        assert_eq!(engine.options().multi_pv, 4);
+        assert!(matches!(engine.set_option("MultiPV", "0"), Err(EngineError::InvalidOptionValue { .. })));
+        let result = engine.search(SearchLimits::to_depth(2)).unwrap().unwrap();
+        assert_eq!(result.lines.len(), 4);
+        assert_eq!(result.lines[0].moves[0], result.best_move);
+    }
+
+    #[test]
+    fn skill_levels() {
+        assert_eq!(Skill::default().level(), MAX_LEVEL);
+        assert_eq!(Skill::new(50).level(), MAX_LEVEL);
+        assert_eq!(Skill::from_elo(0).level(), 0);
+        assert_eq!(Skill::from_elo(5000), Skill::default());
+        for level in 0..=MAX_LEVEL {
+            assert_eq!(Skill::from_elo(Skill::new(level).elo()).level(), level);
+        }
+        assert!((1..=MAX_LEVEL).all(|level| Skill::new(level).blunder_chance() < Skill::new(level - 1).blunder_chance()));
+        assert_eq!(Skill::default().blunder_chance(), 0.0);
+
+        let limits = SearchLimits::to_depth(8);
+        assert_eq!(Skill::default().limits(limits), limits);
+        let weak = Skill::new(4).limits(limits);
+        assert_eq!(weak.depth, Some(3));
+        assert!(weak.nodes.is_some());
+        assert_eq!(Skill::new(18).limits(SearchLimits::to_depth(2)).depth, Some(2));
+    }
+
+    #[test]
+    fn engine_skill_level() {
+        // Only full strength is sure to take the queen
+        let board = Board::from_fen("q3k3/8/8/8/8/8/6PP/R3K3 w - - 0 1").unwrap();
+        let take = Move::new(Coord::from_notation("a1").unwrap(), Coord::from_notation("a8").unwrap(), None);
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "1").unwrap();
+        engine.set_position(board, &[]).unwrap();
+        let play = |engine: &mut Engine, seed: u64| {
+            engine.seed(seed).unwrap();
+            engine.search(SearchLimits::to_depth(4)).unwrap().unwrap().best_move
+        };
+        assert!((0..20).all(|seed| play(&mut engine, seed) == take));
+
+        engine.set_option("SkillLevel", "0").unwrap();
+        assert_eq!(engine.skill(), Skill::new(0));
+        let moves: Vec<Move> = (0..40).map(|seed| play(&mut engine, seed)).collect();
+        assert!(moves.iter().any(|&mv| mv != take));
+        assert!(moves.iter().all(|&mv| board.is_valid_move(mv)));
+        // The same seed plays the same moves
+        assert_eq!(moves, (0..40).map(|seed| play(&mut engine, seed)).collect::<Vec<_>>());
+
+        engine.set_option("Elo", "2400").unwrap();
+        assert!(engine.skill().is_full_strength());
+        assert!(matches!(engine.set_option("SkillLevel", "21"), Err(EngineError::InvalidOptionValue { .. })));
+        assert!(matches!(engine.set_option("Seed", "x"), Err(EngineError::InvalidOptionValue { .. })));
+    }
+
+    #[test]
+    fn engine_scores_repetitions_as_draws() {
+        let mv = |from: &str, to: &str| Move::new(Coord::from_notation(from).unwrap(), Coord::from_notation(to).unwrap(), None);
+        let knight_out = mv("g1", "f3");
+        let moves = [knight_out, mv("g8", "f6"), mv("f3", "g1"), mv("f6", "g8")];
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "1").unwrap();
+        engine.set_option("MultiPV", "20").unwrap();
+        engine.set_option("Contempt", "50").unwrap();
+        let knight_out_score = |engine: &mut Engine| {
+            let result = engine.search(SearchLimits::to_depth(3)).unwrap().unwrap();
+            result.lines.iter().find(|line| line.moves[0] == knight_out).unwrap().score
+        };
+
+        // Nf3 repeats the position after the first move, which is worth a draw minus the contempt
+        engine.set_position(Board::default(), &moves).unwrap();
+        assert_eq!(knight_out_score(&mut engine), -50);
+        engine.set_position(Board::default(), &[]).unwrap();
+        assert_ne!(knight_out_score(&mut engine), -50);
+
+        // Negative contempt makes the draw the best there is
+        engine.set_option("MultiPV", "1").unwrap();
+        engine.set_option("Contempt", "-200").unwrap();
+        engine.set_position(Board::default(), &moves).unwrap();
+        let result = engine.search(SearchLimits::to_depth(3)).unwrap().unwrap();
+        assert_eq!((result.best_move, result.score), (knight_out, 200));
+        assert!(matches!(engine.set_option("Contempt", "10000"), Err(EngineError::InvalidOptionValue { .. })));
+    }
+
+    #[test]
+    fn search_fifty_move_rule() {
+        let winning = Board::from_fen("7k/8/8/8/8/8/8/KQ6 w - - 0 80").unwrap();
+        let result = search(&winning, &SearchLimits::to_depth(3), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert!(result.score > 500);
+        // Every move ends the game in a draw
+        let drawn = Board::from_fen("7k/8/8/8/8/8/8/KQ6 w - - 99 80").unwrap();
+        let result = search(&drawn, &SearchLimits::to_depth(3), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(result.score, DRAW);
+        // Unless it mates
+        let mate = Board::from_fen("7k/8/6K1/8/8/8/8/Q7 w - - 99 80").unwrap();
+        let result = search(&mate, &SearchLimits::to_depth(3), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(result.score(), Score::Mate(1));
+    }
+
+    #[test]
+    fn principal_variation_search_and_aspiration() {
+        let board = Board::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let plain = SearchOptions { principal_variation_search: false, aspiration_windows: false, ..Default::default() };
+        let limits = SearchLimits::to_depth(5);
+        let without = search(&board, &limits, plain, &StopSignal::new()).unwrap();
+        let with = search(&board, &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert!(with.nodes < without.nodes);
+        assert!(board.is_valid_move(with.best_move));
+
+        // The score jumps to a mate once the search sees it, which falls far outside the window
+        let board = Board::from_fen("r3r1k1/5ppp/2Q5/2pP4/1P6/2P2N2/P2P1PPP/R3R1K1 w - - 0 1").unwrap();
+        for aspiration_windows in [false, true] {
+            let options = SearchOptions { aspiration_windows, ..Default::default() };
+            let result = search(&board, &SearchLimits::to_depth(6), options, &StopSignal::new()).unwrap();
+            assert_eq!(result.score(), Score::Mate(2));
+            assert_eq!(result.depth, 6);
+        }
+
+        let mut engine = Engine::new();
+        engine.set_option("PrincipalVariationSearch", "off").unwrap();
+        engine.set_option("AspirationWindows", "false").unwrap();
+        assert!(!engine.options().principal_variation_search && !engine.options().aspiration_windows);
+    }
+
+    #[test]
+    fn search_extensions_see_past_the_horizon() {
+        // A mate in three, one ply deeper than the search goes
+        let board = Board::from_fen("5rk1/1b3ppp/8/2RN4/8/8/2Q2PPP/6K1 w - - 0 1").unwrap();
+        let limits = SearchLimits::to_depth(4);
+        let extended = search(&board, &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(extended.score(), Score::Mate(3));
+        let options = SearchOptions { extensions: false, ..Default::default() };
+        let fixed = search(&board, &limits, options, &StopSignal::new()).unwrap();
+        assert!(!is_mate_score(fixed.score));
+
+        let mut engine = Engine::new();
+        engine.set_option("Extensions", "off").unwrap();
+        assert!(!engine.options().extensions);
+    }
+
+    #[test]
+    fn solver_mate_in_two() {
+        let mv = |from: &str, to: &str| Move::new(Coord::from_notation(from).unwrap(), Coord::from_notation(to).unwrap(), None);
+        let board = Board::from_fen("r3r1k1/5ppp/2Q5/2pP4/1P6/2P2N2/P2P1PPP/R3R1K1 w - - 0 1").unwrap();
+        assert!(!solve_mate(&board, 1, SolverOptions::default()).is_solved());
+
+        // Either piece can take on e8 first, so the problem is cooked
+        let solution = solve_mate(&board, 2, SolverOptions::default());
+        assert!(solution.is_solved() && !solution.is_unique());
+        let keys: Vec<Move> = solution.keys.iter().map(|key| key.mv).collect();
+        assert_eq!(keys, [mv("c6", "e8"), mv("e1", "e8")]);
+        let queen = &solution.keys[0];
+        assert_eq!(queen.mate_in, 2);
+        assert_eq!(queen.defences.len(), 1);
+        assert_eq!(queen.defences[0].mv, mv("a8", "e8"));
+        let mate = &queen.defences[0].continuations;
+        assert_eq!(mate.len(), 1);
+        assert_eq!((mate[0].mv, mate[0].mate_in), (mv("e1", "e8"), 1));
+        assert!(mate[0].defences.is_empty());
+        assert_eq!(solve_mate(&board, 2, SolverOptions { checks_only: true }).keys, solution.keys);
+    }
+
+    #[test]
+    fn solver_quiet_key_and_disproof() {
+        let board = Board::from_fen("k7/8/2K5/8/8/8/8/1R6 w - - 0 1").unwrap();
+        let solution = solve_mate(&board, 2, SolverOptions::default());
+        assert!(solution.is_unique());
+        let key = &solution.keys[0];
+        assert_eq!(key.mv, Move::new(Coord::from_notation("c6").unwrap(), Coord::from_notation("c7").unwrap(), None));
+        // Every reply is answered, here the only one
+        assert_eq!(key.defences.len(), 1);
+        assert!(key.defences.iter().all(|defence| !defence.continuations.is_empty()));
+        // Checks alone don't find a quiet key
+        assert!(!solve_mate(&board, 2, SolverOptions { checks_only: true }).is_solved());
+
+        let solution = solve_mate(&Board::default(), 2, SolverOptions::default());
+        assert!(!solution.is_solved());
+        assert!(solution.nodes > 0);
+    }
+
+    #[test]
+    fn solver_helpmates() {
+        let mv = |from: &str, to: &str| Move::new(Coord::from_notation(from).unwrap(), Coord::from_notation(to).unwrap(), None);
+        // Either rook mates after Kg8, a dual
+        let board = Board::from_fen("7k/8/6K1/8/8/8/8/RR6 b - - 0 1").unwrap();
+        let solution = solve_helpmate(&board, 1);
+        assert_eq!(solution.solutions, [vec![mv("h8", "g8"), mv("a1", "a8")], vec![mv("h8", "g8"), mv("b1", "b8")]]);
+        assert!(solution.has_duals() && !solution.is_cooked());
+
+        // Two unrelated solutions, a cook
+        let board = Board::from_fen("4R3/8/8/8/2p2kp1/6R1/7K/8 b - - 0 1").unwrap();
+        assert!(!solve_helpmate(&board, 1).is_solved());
+        let solution = solve_helpmate(&board, 2);
+        assert_eq!(solution.solutions.len(), 2);
+        assert!(solution.solutions.contains(&vec![mv("c4", "c3"), mv("g3", "g1"), mv("f4", "f3"), mv("g1", "f1")]));
+        assert!(solution.solutions.contains(&vec![mv("f4", "g5"), mv("g3", "g4"), mv("g5", "h6"), mv("e8", "h8")]));
+        assert!(solution.is_cooked() && !solution.has_duals());
+    }
+
+    #[test]
+    fn solver_selfmate() {
+        let board = Board::from_fen("8/6R1/8/8/8/8/2RKp3/k7 w - - 0 1").unwrap();
+        let solution = solve_selfmate(&board, 1);
+        assert!(solution.is_unique() && !solution.has_duals());
+        let key = &solution.keys[0];
+        assert_eq!(key.mv, Move::new(Coord::from_notation("d2").unwrap(), Coord::from_notation("c1").unwrap(), None));
+        assert_eq!(key.mate_in, 1);
+        // Black's only move promotes with mate
+        assert_eq!(key.defences.len(), 1);
+        assert!(key.defences[0].continuations.is_empty());
+
+        assert!(!solve_selfmate(&Board::default(), 1).is_solved());
+    }
+
+    #[test]
+    fn time_budget_from_clock() {
+        let limits = SearchLimits {
+            wtime: Some(std::time::Duration::from_secs(60)),
+            btime: Some(std::time::Duration::from_secs(1)),
+            winc: Some(std::time::Duration::from_secs(1)),
+            movestogo: Some(20),
+            ..Default::default()
+        };
+        let (soft, hard) = limits.time_budget(PieceColor::White).unwrap();
+        assert!(soft >= std::time::Duration::from_secs(3) && soft <= hard);
+        let (_, hard) = limits.time_budget(PieceColor::Black).unwrap();
+        assert!(hard <= std::time::Duration::from_millis(500));
+        assert!(SearchLimits::infinite().time_budget(PieceColor::White).is_none());
+    }
+
+    #[test]
+    fn search_pool_finds_mate() {
+        let board = Board::from_fen("r3r1k1/5ppp/2Q5/2pP4/1P6/2P2N2/P2P1PPP/R3R1K1 w - - 0 1").unwrap();
+        let pool = SearchPool::new(4, 16);
+        let result = pool.search(&board, &[], &SearchLimits::to_depth(4), SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(pool.threads(), 4);
+        assert_eq!(result.score(), Score::Mate(2));
+    }
+
+    #[test]
+    fn search_pool_single_thread_deterministic() {
+        let board = Board::from_fen("r4k1r/bp2pppp/n1p5/1bqpN3/4P3/1QN5/PPPP1PPP/R1BBK2R w KQ - 3 3").unwrap();
+        let limits = SearchLimits::to_depth(4);
+        let first = SearchPool::new(1, 16).search(&board, &[], &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        let second = SearchPool::new(1, 16).search(&board, &[], &limits, SearchOptions::default(), &StopSignal::new()).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn search_pool_reused_between_searches() {
+        let mut pool = SearchPool::new(2, 16);
+        let mut board = Board::default();
+        for _ in 0..4 {
+            let result = pool.search(&board, &[], &SearchLimits::to_depth(3), SearchOptions::default(), &StopSignal::new()).unwrap();
+            board.do_move_from_coord(result.best_move).unwrap();
+        }
+        pool.set_threads(1);
+        assert!(pool.search(&board, &[], &SearchLimits::to_depth(2), SearchOptions::default(), &StopSignal::new()).is_some());
+    }
+
+    #[test]
+    fn engine_go_and_wait() {
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "2").unwrap();
+        engine.set_position(Board::from_fen("rnbqkb1r/1ppp1ppp/p4n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 0 1").unwrap(), &[]).unwrap();
+        engine.go(SearchLimits::to_depth(3)).unwrap();
+        assert!(matches!(engine.go(SearchLimits::to_depth(3)), Err(EngineError::SearchInProgress)));
+        let result = engine.wait().unwrap().unwrap();
+        assert_eq!(result.score(), Score::Mate(1));
+        assert_eq!(engine.threads(), 2);
+    }
+
+    #[test]
+    fn engine_idle_once_search_finishes() {
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "3").unwrap();
+        engine.go(SearchLimits::infinite()).unwrap();
+        // Answered while the search holds the pool
+        assert_eq!(engine.threads(), 3);
+        engine.stop();
+        while engine.is_searching() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // The result was never collected, the engine takes new settings anyway
+        engine.set_position(Board::from_fen("7k/R7/1R6/8/8/8/8/K7 b - - 0 1").unwrap(), &[]).unwrap();
+        assert!(engine.wait().is_err());
+        let result = engine.search(SearchLimits::to_depth(2)).unwrap().unwrap();
+        assert_eq!(result.score(), Score::Mate(-1));
+    }
+
+    #[test]
+    fn engine_stop() {
+        let mut engine = Engine::new();
+        engine.go(SearchLimits::infinite()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        engine.stop();
+        let result = engine.wait().unwrap().unwrap();
+        assert!(Board::default().is_valid_move(result.best_move));
+        assert!(!engine.is_searching());
+    }
+
+    #[test]
+    fn engine_set_position_with_moves() {
+        let mut engine = Engine::new();
+        let moves = [
+            Move::new(Coord::from_notation("e2").unwrap(), Coord::from_notation("e4").unwrap(), None),
+            Move::new(Coord::from_notation("e7").unwrap(), Coord::from_notation("e5").unwrap(), None),
+        ];
+        engine.set_position(Board::default(), &moves).unwrap();
+        assert_eq!(engine.position().turn, PieceColor::White);
+
+        let illegal = [Move::new(Coord::from_notation("e2").unwrap(), Coord::from_notation("e5").unwrap(), None)];
+        assert!(matches!(engine.set_position(Board::default(), &illegal), Err(EngineError::Position(_))));
+
+        engine.new_game().unwrap();
+        assert!(*engine.position() == Board::default());
+    }
+
+    #[test]
+    fn engine_set_option() {
+        let mut engine = Engine::new();
+        engine.set_option("nullmove", "false").unwrap();
+        assert!(!engine.options().null_move);
+        engine.set_option("Hash", "8").unwrap();
+        assert!(matches!(engine.set_option("Threads", "zero"), Err(EngineError::InvalidOptionValue { .. })));
+        assert!(matches!(engine.set_option("Ponder", "true"), Err(EngineError::UnknownOption(_))));
+    }
+
+    #[test]
+    fn game_phase_from_material() {
+        assert_eq!(game_phase(&Board::default()), PHASE_MAX);
+        assert_eq!(game_phase(&Board::from_fen("4k3/pppp4/8/8/8/8/4PPPP/4K3 w - - 0 1").unwrap()), 0);
+        assert_eq!(game_phase(&Board::from_fen("3rk3/8/8/8/8/8/8/2N1K3 w - - 0 1").unwrap()), 3);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn weighted_value_is_the_middlegame_value() {
+        let knight = Piece { color: PieceColor::Black, kind: PieceKind::Knight };
+        let d5 = Coord::from_notation("d5").unwrap();
+        assert_eq!(crate::engine::get_weighted_value(knight, d5), crate::evaluation::piece_square_value(knight, d5).mg);
+    }
+
+    #[test]
+    fn evaluation_symmetric() {
+        let white = Board::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let black = Board::from_fen("rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R b KQkq - 4 4").unwrap();
+        assert_eq!(evaluate(&white), evaluate(&black));
+        assert_eq!(evaluate(&Board::default()), 0);
+    }
+
+    #[test]
+    fn evaluation_king_centralizes_in_endgame() {
+        let central = Board::from_fen("8/5k2/8/8/4K3/8/3P4/8 w - - 0 1").unwrap();
+        let corner = Board::from_fen("8/5k2/8/8/8/8/3P4/K7 w - - 0 1").unwrap();
+        assert!(evaluate(&central) > evaluate(&corner));
+    }
+
+    #[test]
+    fn evaluation_king_sheltered_in_middlegame() {
+        let castled = Board::from_fen("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQ1RK1 w - - 0 1").unwrap();
+        let exposed = Board::from_fen("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/3PKN2/PPP2PPP/RNBQ1R2 w - - 0 1").unwrap();
+        assert!(evaluate(&castled) > evaluate(&exposed));
+    }
+
+    #[test]
+    fn evaluation_mop_up() {
+        // The bare king belongs in the corner, with the other king close by
+        let center = Board::from_fen("8/8/8/4k3/8/2K5/8/7R w - - 0 1").unwrap();
+        let corner = Board::from_fen("k7/8/8/8/8/2K5/8/7R w - - 0 1").unwrap();
+        let close = Board::from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1").unwrap();
+        assert!(evaluate(&corner) > evaluate(&center));
+        assert!(evaluate(&close) > evaluate(&corner));
+        assert!(evaluate(&center) > 500);
+        // KBNK mates only in a corner of the bishop's color
+        let right = Board::from_fen("k7/8/2K5/8/2B5/8/8/6N1 w - - 0 1").unwrap();
+        let wrong = Board::from_fen("7k/8/5K2/8/2B5/8/8/6N1 w - - 0 1").unwrap();
+        assert!(evaluate(&right) > evaluate(&wrong));
+        let flipped = Board::from_fen("6n1/8/8/2b5/8/2k5/8/K7 b - - 0 1").unwrap();
+        assert_eq!(evaluate(&flipped), evaluate(&right));
+    }
+
+    #[test]
+    fn evaluation_drawish_endings() {
+        // The dark-squared bishop can't drive the king out of a8
+        let wrong_bishop = Board::from_fen("k7/8/8/8/8/8/P7/2B1K3 w - - 0 1").unwrap();
+        let right_bishop = Board::from_fen("k7/8/8/8/8/8/P7/1B2K3 w - - 0 1").unwrap();
+        assert_eq!(evaluate(&wrong_bishop), 0);
+        assert!(evaluate(&right_bishop) > 300);
+        assert_eq!(evaluate(&Board::from_fen("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap()), 0);
+        assert_eq!(evaluate(&Board::from_fen("4k3/8/8/8/8/8/8/NN2K3 b - - 0 1").unwrap()), 0);
+        assert!(evaluate(&Board::from_fen("4k3/8/8/3b4/8/8/8/R3K3 w - - 0 1").unwrap()) < 100);
+        // A knight can't get the king out of the corner in front of a rook pawn on the seventh, but it
+        // can while the pawn is further back
+        assert_eq!(evaluate(&Board::from_fen("k7/P7/8/8/8/8/8/2N1K3 w - - 0 1").unwrap()), 0);
+        assert_eq!(evaluate(&Board::from_fen("2k5/8/8/8/8/8/7p/5n1K w - - 0 1").unwrap()), 0);
+        assert!(evaluate(&Board::from_fen("k7/8/8/8/8/8/P7/2N1K3 w - - 0 1").unwrap()) > 300);
+        assert!(evaluate(&Board::from_fen("k7/1P6/8/8/8/8/8/2N1K3 w - - 0 1").unwrap()) > 300);
+        // Bishops on opposite colors are worth less than on the same color
+        let opposite = Board::from_fen("4k3/8/8/3b4/8/8/PP6/2B1K3 w - - 0 1").unwrap();
+        let same = Board::from_fen("4k3/8/8/4b3/8/8/PP6/2B1K3 w - - 0 1").unwrap();
+        assert!(evaluate(&opposite) > 0);
+        assert!(evaluate(&opposite) < evaluate(&same));
+    }
+
+    #[test]
+    fn engine_mates_bare_king() {
+        let mut board = Board::from_fen("8/8/8/4k3/8/8/8/4K2R w - - 0 1").unwrap();
+        for _ in 0..60 {
+            if board.get_checkmate() {
+                break;
+            }
+            let result = search(&board, &SearchLimits::to_depth(3), SearchOptions::default(), &StopSignal::new()).unwrap();
+            board.do_move_from_coord(result.best_move).unwrap();
+        }
+        assert!(board.get_checkmate());
+        assert_eq!(board.turn, PieceColor::Black);
+    }
+
+    #[test]
+    fn pawn_structure_passed_pawns() {
+        // e5 is passed, the d-pawn faces the c6 pawn and b2 faces a7
+        let board = Board::from_fen("4k3/p7/2p5/4P3/3P4/8/1P6/4K3 w - - 0 1").unwrap();
+        let structure = PawnStructure::analyze(&board, &EvalParams::default().pawns);
+        let e5 = Coord::from_notation("e5").unwrap();
+        assert_eq!(structure.passed[PieceColor::White as usize], 1 << (e5.y * 8 + e5.x));
+        assert_eq!(structure.passed[PieceColor::Black as usize], 0);
+    }
+
+    #[test]
+    fn pawn_structure_penalties() {
+        let healthy = PawnStructure::analyze(&Board::from_fen("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1").unwrap(), &EvalParams::default().pawns);
+        let doubled = PawnStructure::analyze(&Board::from_fen("4k3/pppp4/8/8/8/1P6/PP1P4/4K3 w - - 0 1").unwrap(), &EvalParams::default().pawns);
+        let isolated = PawnStructure::analyze(&Board::from_fen("4k3/pppp4/8/8/8/8/PP1P4/4K3 w - - 0 1").unwrap(), &EvalParams::default().pawns);
+        assert_eq!(healthy.score.eg, 0);
+        assert!(doubled.score.eg < healthy.score.eg);
+        assert!(isolated.score.eg < 0);
+    }
+
+    #[test]
+    fn passed_pawn_free_path() {
+        let free = Board::from_fen("k7/8/8/4P3/8/8/8/4K3 w - - 0 1").unwrap();
+        let blocked = Board::from_fen("k7/8/4n3/4P3/8/8/8/4K3 w - - 0 1").unwrap();
+        let structure = PawnStructure::analyze(&free, &EvalParams::default().pawns);
+        assert!(structure.passed_pawn_path_bonus(&free, &EvalParams::default().pawns).eg > 0);
+        assert_eq!(structure.passed_pawn_path_bonus(&blocked, &EvalParams::default().pawns).eg, 0);
+    }
+
+    #[test]
+    fn pawn_hash_table_hit() {
+        let mut table = PawnHashTable::new();
+        let board = Board::from_fen("r1bqkbnr/pp1ppppp/2n5/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let first = table.probe(&board, &EvalParams::default().pawns);
+        let mut moved = board;
+        moved.do_move("f1", "b5").unwrap();
+        assert_eq!(moved.pawn_key(), board.pawn_key());
+        assert_eq!(table.probe(&moved, &EvalParams::default().pawns), first);
+        assert_eq!(first, PawnStructure::analyze(&board, &EvalParams::default().pawns));
+    }
+
+    #[test]
+    fn attacks_from_stops_at_blockers() {
+        let board = Board::from_fen("4k3/8/8/8/1p1R4/8/3P4/4K3 w - - 0 1").unwrap();
+        let square = |notation: &str| {
+            let coord = Coord::from_notation(notation).unwrap();
+            1u64 << (coord.y * 8 + coord.x)
+        };
+        let rook = board.attacks_from(Coord::from_notation("d4").unwrap());
+        assert_ne!(rook & square("b4"), 0);
+        assert_eq!(rook & square("a4"), 0);
+        assert_ne!(rook & square("d2"), 0);
+        assert_eq!(rook & square("d1"), 0);
+        assert_eq!(rook.count_ones(), 12);
+        let pawn = board.attacks_from(Coord::from_notation("b4").unwrap());
+        assert_eq!(pawn, square("a3") | square("c3"));
+    }
+
+    #[test]
+    fn activity_bishop_pair_and_open_files() {
+        let pair = Board::from_fen("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1").unwrap();
+        let single = Board::from_fen("4k3/8/8/8/8/8/8/2N1KB2 w - - 0 1").unwrap();
+        assert!(piece_activity(&pair, &EvalParams::default().activity).eg - piece_activity(&single, &EvalParams::default().activity).eg >= 40);
+
+        let open = Board::from_fen("4k3/pp4pp/8/8/8/8/PP4PP/3RK3 w - - 0 1").unwrap();
+        let closed = Board::from_fen("4k3/pp1p2pp/8/8/8/8/PP1P2PP/2R1K3 w - - 0 1").unwrap();
+        assert!(piece_activity(&open, &EvalParams::default().activity).mg > piece_activity(&closed, &EvalParams::default().activity).mg);
+    }
+
+    #[test]
+    fn activity_king_shelter() {
+        let sheltered = Board::from_fen("r5k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+        let exposed = Board::from_fen("r5k1/5ppp/8/8/8/5PPP/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(piece_activity(&sheltered, &EvalParams::default().activity).mg, 0);
+        assert!(piece_activity(&exposed, &EvalParams::default().activity).mg < 0);
+    }
+
+    #[test]
+    fn activity_knight_outpost() {
+        let outpost = Board::from_fen("4k3/pp6/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let chased = Board::from_fen("4k3/pp2p3/8/3N4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert!(piece_activity(&outpost, &EvalParams::default().activity).mg > piece_activity(&chased, &EvalParams::default().activity).mg);
+    }
+
+    /// Counts its calls and scores material backwards, so the search should avoid winning anything
+    struct GiveawayEvaluator {
+        calls: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    }
+
+    impl Evaluator for GiveawayEvaluator {
+        fn evaluate(&mut self, board: &Board) -> i32 {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let mut material = 0;
+            for row in board.board.iter() {
+                for square in row.iter() {
+                    if let Square::Occupied(piece) = square {
+                        let value = piece_value(piece.kind).mg;
+                        material += if piece.color == board.turn { value } else { -value };
+                    }
+                }
+            }
+            -material
+        }
+
+        fn fork(&self) -> Box<dyn Evaluator> {
+            Box::new(GiveawayEvaluator { calls: self.calls.clone() })
+        }
+    }
+
+    #[test]
+    fn search_with_custom_evaluator() {
+        let board = Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+        let limits = SearchLimits::to_depth(2);
+        let capture = Move::new(Coord::from_notation("d1").unwrap(), Coord::from_notation("d5").unwrap(), None);
+
+        let result = search_with_evaluator(&board, &limits, SearchOptions::full_width(), &StopSignal::new(), Box::new(PstEvaluator::new())).unwrap();
+        assert_eq!(result.best_move, capture);
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let evaluator = Box::new(GiveawayEvaluator { calls: calls.clone() });
+        let result = search_with_evaluator(&board, &limits, SearchOptions::full_width(), &StopSignal::new(), evaluator).unwrap();
+        assert_ne!(result.best_move, capture);
+        assert!(calls.load(std::sync::atomic::Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn engine_set_evaluator() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "2").unwrap();
+        engine.set_evaluator(Box::new(GiveawayEvaluator { calls: calls.clone() })).unwrap();
+        engine.set_position(Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap(), &[]).unwrap();
+        let result = engine.search(SearchLimits::to_depth(3)).unwrap().unwrap();
+        assert_ne!(result.best_move.to, Coord::from_notation("d5").unwrap());
+        assert!(calls.load(std::sync::atomic::Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn eval_params_toml_roundtrip() {
+        let params = EvalParams::default();
+        let text = params.to_toml();
+        assert!(text.contains("[psqt.queen]"));
+        assert_eq!(EvalParams::from_toml(&text).unwrap(), params);
+    }
+
+    #[test]
+    fn eval_params_rejects_bad_input() {
+        assert!(matches!(EvalParams::from_toml("material = 3"), Err(ParamsError::Parse(_))));
+        let unknown = EvalParams::default().to_toml() + "\n[extra]\nvalue = 1\n";
+        assert!(matches!(EvalParams::from_toml(&unknown), Err(ParamsError::Parse(_))));
+        let mut params = EvalParams::default();
+        params.activity.king_safety.clear();
+        assert!(matches!(EvalParams::from_toml(&params.to_toml()), Err(ParamsError::Invalid(_))));
+        assert!(matches!(EvalParams::load("/nonexistent/params.toml"), Err(ParamsError::Io(_))));
+    }
+
+    #[test]
+    fn eval_params_black_mirrors_white() {
+        let params = EvalParams::default();
+        for kind in [PieceKind::Pawn, PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook, PieceKind::Queen, PieceKind::King] {
+            for y in 0..8 {
+                for x in 0..8 {
+                    let white = params.piece_square_value(Piece { color: PieceColor::White, kind }, Coord { x, y });
+                    let black = params.piece_square_value(Piece { color: PieceColor::Black, kind }, Coord { x, y: 7 - y });
+                    assert_eq!(white, black);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn eval_params_load_and_save() {
+        let path = std::env::temp_dir().join(format!("eval_params_{}.toml", std::process::id()));
+        let mut params = EvalParams::default();
+        params.material.queen = params.material.queen + params.material.queen;
+        params.save(&path).unwrap();
+        let loaded = EvalParams::load(&path).unwrap();
+        assert_eq!(loaded, params);
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        assert!(evaluate_with(&board, &loaded) > evaluate(&board) + 800);
+
+        let mut engine = Engine::new();
+        engine.set_option("EvalFile", path.to_str().unwrap()).unwrap();
+        assert!(matches!(engine.set_option("EvalFile", "/nonexistent/params.toml"), Err(EngineError::Params(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tuning_position_formats() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        assert_eq!(TuningPosition::parse(&format!("{fen} 1-0")).unwrap().result, 1.0);
+        assert_eq!(TuningPosition::parse(&format!("{fen} [0.5]")).unwrap().result, 0.5);
+        assert_eq!(TuningPosition::parse("4k3/8/8/8/8/8/4P3/4K3 w - - c9 \"0-1\";").unwrap().result, 0.0);
+        assert!(TuningPosition::parse(&format!("{fen} 2-0")).is_err());
+        assert!(TuningPosition::parse("").is_err());
+    }
+
+    #[test]
+    fn eval_params_weights_roundtrip() {
+        let params = EvalParams::default();
+        let mut weights = params.weights();
+        assert_eq!(&weights[..2], &[100, 120]);
+        weights[0] = 110;
+        let mut changed = params.clone();
+        changed.set_weights(&weights);
+        assert_eq!(changed.material.pawn.mg, 110);
+        assert_eq!(changed.weights(), weights);
+    }
+
+    #[test]
+    fn tuner_reduces_error() {
+        let lines = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 1/2-1/2",
+            "4k3/8/8/8/8/8/3PP3/4K3 w - - 0 1 1-0",
+            "4k3/8/8/8/8/8/PPPP4/4K3 b - - 0 1 1-0",
+            "4k3/pppp4/8/8/8/8/8/4K3 w - - 0 1 0-1",
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1 1-0",
+            "r3k3/8/8/8/8/8/8/4K3 b - - 0 1 0-1",
+        ];
+        let positions = lines.iter().map(|line| TuningPosition::parse(line).unwrap()).collect();
+        let options = TunerOptions { threads: 2, max_iterations: 1, step: 5 };
+        let mut tuner = Tuner::new(positions, options);
+        let params = EvalParams::default();
+        let start = tuner.fit_scaling(&params);
+        assert!(tuner.scaling > 0.05 && tuner.scaling < 5.0);
+
+        let mut reported = Vec::new();
+        let tuned = tuner.tune(params.clone(), |iteration, error| reported.push((iteration, error)));
+        assert_eq!(reported.len(), 1);
+        assert!(tuner.error(&tuned) < start);
+        assert_eq!(tuner.error(&tuned), reported[0].1);
+        assert_ne!(tuned, params);
+    }
+
+    fn random_network(hidden: usize, seed: u64) -> Network {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut values = |n: usize| (0..n).map(|_| rng.gen_range(-20..=20)).collect::<Vec<i16>>();
+        let feature_weights = values(FEATURES * hidden);
+        let feature_biases = values(hidden).iter().map(|b| b + 40).collect();
+        let output_weights = values(2 * hidden);
+        Network::new(hidden, feature_weights, feature_biases, output_weights, 0).unwrap()
+    }
+
+    /// One hidden unit counting the side's own material in pawns, so the output is the material balance
+    fn material_network() -> Network {
+        let values = [1, 3, 3, 5, 9];
+        let feature_weights = (0..FEATURES)
+            .map(|feature| {
+                let piece = (feature / 64) % 10;
+                if piece % 2 == 0 { values[piece / 2] } else { 0 }
+            })
+            .collect();
+        Network::new(1, feature_weights, vec![0], vec![4080, -4080], 0).unwrap()
+    }
+
+    #[test]
+    fn nnue_network_file_roundtrip() {
+        let network = random_network(4, 1);
+        let bytes = network.to_bytes();
+        assert_eq!(Network::from_bytes(&bytes).unwrap(), network);
+        assert!(matches!(Network::from_bytes(&bytes[..bytes.len() - 1]), Err(NnueError::Format(_))));
+        assert!(matches!(Network::from_bytes(b"NOPE"), Err(NnueError::Format(_))));
+        assert!(Network::new(4, vec![0; 10], vec![0; 4], vec![0; 8], 0).is_err());
+    }
+
+    #[test]
+    fn nnue_material_network() {
+        let mut evaluator = NnueEvaluator::new(material_network());
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/3QK3 w - - 0 1").unwrap();
+        assert_eq!(evaluator.evaluate(&board), 1000);
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/3QK3 b - - 0 1").unwrap();
+        assert_eq!(evaluator.evaluate(&board), -1000);
+        assert_eq!(evaluator.evaluate(&Board::default()), 0);
+    }
+
+    #[test]
+    fn nnue_incremental_matches_refresh() {
+        let network = random_network(16, 7);
+        let mut incremental = NnueEvaluator::new(network.clone());
+        let mut fresh = NnueEvaluator::new(network);
+        // Castling, a capture, en passant, king moves and a promotion
+        let moves = [
+            ("e2", "e4"), ("g8", "f6"), ("g1", "f3"), ("f6", "e4"), ("f1", "e2"), ("d7", "d5"),
+            ("e1", "g1"), ("d5", "d4"), ("c2", "c4"), ("d4", "c3"), ("b2", "c3"), ("e8", "d7"),
+        ];
+        let mut board = Board::default();
+        let mut line = vec![board];
+        incremental.evaluate(&board);
+        for (from, to) in moves {
+            let mut child = board;
+            child.do_move(from, to).unwrap();
+            incremental.push(&board, &child);
+            assert_eq!(incremental.evaluate(&child), fresh.evaluate(&child), "after {from}{to}");
+            line.push(child);
+            board = child;
+        }
+        let mut null = board;
+        null.make_null_move();
+        incremental.push(&board, &null);
+        assert_eq!(incremental.evaluate(&null), fresh.evaluate(&null));
+        incremental.pop();
+        for position in line.iter().rev() {
+            assert_eq!(incremental.evaluate(position), fresh.evaluate(position));
+            incremental.pop();
+        }
+
+        let promotion = Board::from_fen("8/4P3/8/8/8/8/k7/4K3 w - - 0 1").unwrap();
+        let mut child = promotion;
+        child.do_move("e7", "e8").unwrap();
+        incremental.push(&promotion, &child);
+        assert_eq!(incremental.evaluate(&child), fresh.evaluate(&child));
+    }
+
+    #[test]
+    fn engine_nnue_file() {
+        let path = std::env::temp_dir().join(format!("nnue_{}.bin", std::process::id()));
+        material_network().save(&path).unwrap();
+        let mut engine = Engine::new();
+        engine.set_option("NnueFile", path.to_str().unwrap()).unwrap();
+        engine.set_position(Board::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap(), &[]).unwrap();
+        let result = engine.search(SearchLimits::to_depth(3)).unwrap().unwrap();
+        assert_eq!(result.best_move.to, Coord::from_notation("d5").unwrap());
+        assert!(matches!(engine.set_option("NnueFile", "/nonexistent/net.bin"), Err(EngineError::Network(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fen_roundtrip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 7 23",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 40",
+            "8/8/8/8/8/8/8/K6k b - - 12 70",
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+        }
+        let mut board = Board::default();
+        board.do_move("e2", "e4").unwrap();
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    }
+
+    #[test]
+    fn halfmove_clock_resets_on_captures_and_pawn_moves() {
+        let mut board = Board::default();
+        board.do_move("g1", "f3").unwrap();
+        board.do_move("g8", "f6").unwrap();
+        assert_eq!(board.halfmove_clock, 2);
+        board.do_move("e2", "e4").unwrap();
+        assert_eq!(board.halfmove_clock, 0);
+        board.do_move("b8", "c6").unwrap();
+        assert_eq!(board.halfmove_clock, 1);
+        board.do_move("f1", "b5").unwrap();
+        assert_eq!(board.halfmove_clock, 2);
+        // A piece taking a pawn
+        board.do_move("f6", "e4").unwrap();
+        assert_eq!(board.halfmove_clock, 0);
+
+        // Castling counts as a quiet move, en passant as a capture
+        let mut board = Board::from_fen("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 9 30").unwrap();
+        board.do_move("e1", "g1").unwrap();
+        assert_eq!(board.halfmove_clock, 10);
+        board.do_move("d5", "d4").unwrap();
+        assert_eq!(board.halfmove_clock, 0);
+        let mut board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 9 40").unwrap();
+        board.do_move("e5", "d6").unwrap();
+        assert_eq!(board.halfmove_clock, 0);
+    }
+
+    #[test]
+    fn fullmove_number_increments_after_black() {
+        let mut board = Board::default();
+        assert_eq!(board.fullmove_number, 1);
+        board.do_move("e2", "e4").unwrap();
+        assert_eq!(board.fullmove_number, 1);
+        board.do_move("e7", "e5").unwrap();
+        assert_eq!(board.fullmove_number, 2);
+        board.do_move("g1", "f3").unwrap();
+        assert_eq!(board.fullmove_number, 2);
+
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 17").unwrap();
+        board.do_move("e8", "c8").unwrap();
+        assert_eq!((board.fullmove_number, board.halfmove_clock), (18, 1));
+    }
+
+    #[test]
+    fn packed_position_roundtrip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 17",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 40",
+            "8/8/8/8/3pP3/8/8/K6k b - e3 0 9",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(PackedPosition::pack(&board).unpack().unwrap(), board, "{fen}");
+        }
+        let record = TrainingRecord { board: Board::default(), score: -42, result: GameResult::BlackWin };
+        let bytes = record.to_bytes();
+        assert_eq!(TrainingRecord::from_bytes(&bytes).unwrap(), record);
+        assert!(read_records(&bytes[..31]).is_err());
+        assert_eq!(read_records(&bytes[..]).unwrap(), vec![record]);
+    }
+
+    #[test]
+    fn self_play_generates_records() {
+        let options = SelfPlayOptions { games: 2, threads: 2, nodes: 200, max_plies: 14, hash_mb: 1, ..Default::default() };
+        let mut out = Vec::new();
+        let stats = SelfPlay::new(options).generate(&mut out).unwrap();
+        assert_eq!(stats.games, 2);
+        assert_eq!(stats.white_wins + stats.draws + stats.black_wins, 2);
+        let records = read_records(&out[..]).unwrap();
+        assert_eq!(records.len(), stats.positions);
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|record| !record.board.get_check()));
+
+        let pool = SearchPool::new(1, 1);
+        let self_play = SelfPlay::new(options);
+        assert_eq!(self_play.play_game(&pool, 5), self_play.play_game(&pool, 5));
+    }
+
+    #[test]
+    fn polyglot_keys() {
+        let mut board = Board::default();
+        assert_eq!(polyglot_key(&board), 0x463b96181691fc9c);
+        for (mv, key) in [
+            ("e2e4", 0x823c9b50fd114196),
+            ("d7d5", 0x0756b94461c50fb0),
+            ("e4e5", 0x662fafb965db29d4),
+            ("f7f5", 0x22a48b5a8e47ff78),
+            ("e1e2", 0x652a607ca3f242c1),
+            ("e8f7", 0x00fdd303c946bdd9),
+        ] {
+            let (from, to) = mv.split_at(2);
+            board.do_move(from, to).unwrap();
+            assert_eq!(polyglot_key(&board), key, "{mv}");
+        }
+        let board = Board::from_fen("rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3").unwrap();
+        assert_eq!(polyglot_key(&board), 0x3c8123ea7b067637);
+        let board = Board::from_fen("rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 1 4").unwrap();
+        assert_eq!(polyglot_key(&board), 0x5c3f9b829b279560);
+        // No pawn can take en passant, so the square doesn't count
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(polyglot_key(&board), 0x823c9b50fd114196);
+    }
+
+    #[test]
+    fn polyglot_move_encoding() {
+        let board = Board::from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let short = Move::new(Coord::from_notation("e1").unwrap(), Coord::from_notation("g1").unwrap(), None);
+        // e1h1: from e1 (file 4, rank 0) to h1 (file 7, rank 0)
+        assert_eq!(encode_move(&board, short), 4 << 6 | 7);
+        assert_eq!(decode_move(&board, 4 << 6 | 7), short);
+        let long = Move::new(Coord::from_notation("e1").unwrap(), Coord::from_notation("c1").unwrap(), None);
+        assert_eq!(decode_move(&board, encode_move(&board, long)), long);
+        let promotion = Move::new(Coord::from_notation("b7").unwrap(), Coord::from_notation("a8").unwrap(), Some(PieceKind::Knight));
+        assert_eq!(encode_move(&board, promotion), 1 << 12 | 6 << 9 | 1 << 6 | 7 << 3);
+        assert_eq!(decode_move(&board, encode_move(&board, promotion)), promotion);
+        // A king move next to its rook is not castling
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4KR2 w - - 0 1").unwrap();
+        let mv = Move::new(Coord::from_notation("e1").unwrap(), Coord::from_notation("e2").unwrap(), None);
+        assert_eq!(decode_move(&board, encode_move(&board, mv)), mv);
+    }
+
+    fn test_book() -> Vec<u8> {
+        let board = Board::default();
+        let key = polyglot_key(&board);
+        let entry = |notation: &str, weight| {
+            let mv = board.parse_move(notation).unwrap();
+            BookEntry { key, mv: encode_move(&board, mv), weight, learn: 0 }
+        };
+        let mut entries = [
+            BookEntry { key: key - 1, mv: 0, weight: 1, learn: 0 },
+            entry("e2e4", 10),
+            entry("d2d4", 30),
+            entry("c2c4", 0),
+            // Not legal in the starting position
+            BookEntry { key, mv: encode_move(&board, Move::new(Coord::from_notation("e2").unwrap(), Coord::from_notation("e5").unwrap(), None)), weight: 1000, learn: 0 },
+            BookEntry { key: key + 1, mv: 0, weight: 1, learn: 0 },
+        ];
+        entries.sort_by_key(|entry| entry.key);
+        entries.iter().flat_map(|entry| entry.to_bytes()).collect()
+    }
+
+    #[test]
+    fn polyglot_book_lookup() {
+        let bytes = test_book();
+        let book = Book::from_bytes(&bytes).unwrap();
+        assert_eq!(book.len(), 6);
+        let board = Board::default();
+        assert_eq!(book.entries(&board).len(), 4);
+        assert_eq!(book.moves(&board).len(), 3);
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(book.choose(&board, BookSelection::Best, &mut rng), Some(board.parse_move("d2d4").unwrap()));
+        let mut picks = 0;
+        for _ in 0..200 {
+            let mv = book.choose(&board, BookSelection::Weighted, &mut rng).unwrap();
+            assert_ne!(mv, board.parse_move("c2c4").unwrap());
+            picks += (mv == board.parse_move("d2d4").unwrap()) as usize;
+        }
+        assert!((120..190).contains(&picks), "{picks}");
+        let mut other = board;
+        other.do_move("g1", "f3").unwrap();
+        assert_eq!(book.choose(&other, BookSelection::Best, &mut rng), None);
+
+        assert!(Book::from_bytes(&bytes[..20]).is_err());
+        let mut unsorted = bytes[16..].to_vec();
+        unsorted.extend_from_slice(&bytes[..16]);
+        assert!(Book::from_bytes(&unsorted).is_err());
+    }
+
+    #[test]
+    fn engine_plays_from_book() {
+        let path = std::env::temp_dir().join(format!("book_{}.bin", std::process::id()));
+        std::fs::write(&path, test_book()).unwrap();
+        let mut engine = Engine::new();
+        engine.set_option("BookFile", path.to_str().unwrap()).unwrap();
+        engine.set_option("BookSelection", "best").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let result = engine.search(SearchLimits::to_depth(3)).unwrap().unwrap();
+        assert_eq!(result.best_move, Board::default().parse_move("d2d4").unwrap());
+        assert_eq!(result.depth, 0);
+
+        engine.set_option("BookFile", "").unwrap();
+        assert!(engine.search(SearchLimits::to_depth(2)).unwrap().unwrap().depth > 0);
+        assert!(matches!(engine.set_option("BookSelection", "worst"), Err(EngineError::InvalidOptionValue { .. })));
+    }
+
+    const TEST_PGN: &str = r#"[Event "Club \"Open\""]
+[White "A"]
+[Black "B"]
+[WhiteElo "2400"]
+[BlackElo "2300"]
+
+1. e4 e5 {Open game; the main line} 2. Nf3 (2. f4 exf4 (2... d5) 3. Nf3) Nc6 3.Bb5 a6 $1 4. Ba4!? Nf6
+; a rest of line comment
+5. O-O 1-0
+
+[Event "Casual"]
+[WhiteElo "2100"]
+[BlackElo "2500"]
+
+1.d4 d5 2.c4 e6 *
+
+[Event "No result"]
+
+1. e4 c5 2. Nf3
+[Event "Last"]
+[Result "0-1"]
+
+1. e4 e5 0-1
+"#;
+
+    #[test]
+    fn pgn_reader() {
+        let games: Vec<_> = read_games(TEST_PGN.as_bytes()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(games.len(), 4);
+        assert_eq!(games[0].tag("Event"), Some("Club \"Open\""));
+        assert_eq!(games[0].rating(PieceColor::Black), Some(2300));
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4!?", "Nf6", "O-O"]);
+        assert_eq!(games[0].result, Some(GameResult::WhiteWin));
+        assert_eq!(games[1].moves, ["d4", "d5", "c4", "e6"]);
+        assert_eq!(games[1].result, None);
+        assert_eq!(games[2].moves, ["e4", "c5", "Nf3"]);
+        assert_eq!(games[2].result, None);
+        assert_eq!(games[3].tag("Event"), Some("Last"));
+        assert_eq!(games[3].result, Some(GameResult::BlackWin));
+        assert!(read_games(&b"[Event broken]\n1. e4 *\n"[..]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn san_parsing() {
+        let board = Board::from_fen("r3k2r/1P6/8/8/8/2N3N1/8/R3K2R w KQkq - 0 1").unwrap();
+        let parse = |san| parse_san(&board, san);
+        assert_eq!(parse("O-O").unwrap(), board.parse_move("e1g1").unwrap());
+        assert_eq!(parse("O-O-O+").unwrap(), board.parse_move("e1c1").unwrap());
+        assert_eq!(parse("Nce4").unwrap(), board.parse_move("c3e4").unwrap());
+        assert_eq!(parse("Nge4").unwrap(), board.parse_move("g3e4").unwrap());
+        assert!(parse("Ne4").unwrap_err().contains("Ambiguous"));
+        assert_eq!(parse("bxa8=Q#").unwrap(), board.parse_move("b7a8").unwrap());
+        assert_eq!(parse("b8Q").unwrap(), board.parse_move("b7b8").unwrap());
+        assert_eq!(parse("b8=N").unwrap(), Move::new(Coord::from_notation("b7").unwrap(), Coord::from_notation("b8").unwrap(), Some(PieceKind::Knight)));
+        assert_eq!(parse("bxa8R").unwrap().promote, Some(PieceKind::Rook));
+        assert!(parse("Rb8=N").is_err());
+        assert!(parse("Ra3").is_ok());
+        assert!(parse("Bb2").is_err());
+        assert!(parse("Kd3").is_err());
+        assert!(parse("zz").is_err());
+    }
+
+    #[test]
+    fn book_builder() {
+        let games: Vec<_> = read_games(TEST_PGN.as_bytes()).collect::<Result<_, _>>().unwrap();
+        let start = Board::default();
+        let build = |options: BookBuilderOptions| {
+            let mut builder = BookBuilder::new(options);
+            let added = games.iter().filter(|game| builder.add_game(game).unwrap()).count();
+            assert_eq!(added, builder.games());
+            (added, builder.build())
+        };
+        let weights = |book: &Book, board: &Board| {
+            let mut moves = book.moves(board);
+            moves.sort_by_key(|&(mv, _)| (mv.from.x, mv.from.y, mv.to.x, mv.to.y));
+            moves
+        };
+        let e4 = start.parse_move("e2e4").unwrap();
+        let d4 = start.parse_move("d2d4").unwrap();
+
+        let (added, book) = build(BookBuilderOptions { weighting: BookWeighting::Games, ..Default::default() });
+        assert_eq!(added, 4);
+        assert_eq!(weights(&book, &start), [(d4, 1), (e4, 3)]);
+        let mut after_e4 = start;
+        after_e4.do_move("e2", "e4").unwrap();
+        assert_eq!(book.moves(&after_e4).len(), 2);
+        let round_trip = Book::from_bytes(&book.to_bytes()).unwrap();
+        assert_eq!(round_trip, book);
+
+        // Wins count two, draws one and losses or unknown results nothing
+        let (_, book) = build(BookBuilderOptions::default());
+        assert_eq!(weights(&book, &start), [(e4, 2)]);
+        assert_eq!(weights(&book, &after_e4), [(after_e4.parse_move("e7e5").unwrap(), 2)]);
+
+        let (_, book) = build(BookBuilderOptions { min_games: 2, max_ply: 1, weighting: BookWeighting::Games, ..Default::default() });
+        assert_eq!(weights(&book, &start), [(e4, 3)]);
+        assert_eq!(book.len(), 1);
+
+        let (added, book) = build(BookBuilderOptions { min_rating: Some(2200), weighting: BookWeighting::Games, ..Default::default() });
+        assert_eq!(added, 1);
+        assert_eq!(weights(&book, &start), [(e4, 1)]);
+
+        let mut builder = BookBuilder::new(BookBuilderOptions::default());
+        let game = read_games(&b"1. e4 e5 2. Ke3 *\n"[..]).next().unwrap().unwrap();
+        assert!(builder.add_game(&game).is_err());
+        assert!(builder.build().is_empty());
+
+        // The opening up to an underpromotion is kept, the board can't go on from there
+        let pgn = b"[FEN \"4k3/1P6/8/8/8/8/8/4K3 w - - 0 1\"]\n1. Kd2 Kd7 2. b8=N+ Kc7 3. Na6+ *\n";
+        let game = read_games(&pgn[..]).next().unwrap().unwrap();
+        let mut builder = BookBuilder::new(BookBuilderOptions { weighting: BookWeighting::Games, ..Default::default() });
+        assert!(builder.add_game(&game).unwrap());
+        let book = builder.build();
+        assert_eq!(book.len(), 3);
+        let mut board = game.start_position().unwrap();
+        board.do_move("e1", "d2").unwrap();
+        board.do_move("e8", "d7").unwrap();
+        let underpromotion = encode_move(&board, Move::new(Coord::from_notation("b7").unwrap(), Coord::from_notation("b8").unwrap(), Some(PieceKind::Knight)));
+        assert_eq!(book.entries(&board).iter().map(|entry| entry.mv).collect::<Vec<_>>(), [underpromotion]);
+        assert!(book.moves(&board).is_empty());
+    }
+
+    #[test]
+    fn book_promotions() {
+        let board = Board::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let push = board.parse_move("b7b8").unwrap();
+        let key = polyglot_key(&board);
+        assert_eq!(decode_move(&board, encode_move(&board, push)).promote, Some(PieceKind::Queen));
+        let entry = |promote| BookEntry { key, mv: encode_move(&board, Move { promote, ..push }), weight: 1, learn: 0 };
+        let book = Book::from_entries(vec![entry(Some(PieceKind::Queen)), entry(Some(PieceKind::Rook))]);
+        assert_eq!(book.moves(&board), [(push, 1)]);
+    }
+
+    /// Directory that is removed when dropped, also when a test fails
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        /// Starts out empty, whatever an earlier run with the same process id left behind
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<std::path::Path> for TempDir {
+        fn as_ref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// KQvK tables where every position has one value: a win with White to move, a loss with Black
+    /// to move, and 10 moves to zeroing
+    fn synthetic_tablebase(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("syzygy_{name}"));
+        // Magic, split flag, group order, pieces of both sides (K, Q, k), padding, then one
+        // single-value sub-table per side to move
+        let wdl = [0x71, 0xE8, 0x23, 0x5D, 0x01, 0x00, 0x66, 0x55, 0xEE, 0x00, 0x80, 4, 0x80, 0];
+        let dtz = [0xD7, 0x66, 0x0C, 0xA5, 0x01, 0x00, 0x06, 0x05, 0x0E, 0x00, 0x80, 10];
+        std::fs::write(dir.join("KQvK.rtbw"), wdl).unwrap();
+        std::fs::write(dir.join("KQvK.rtbz"), dtz).unwrap();
+        std::fs::write(dir.join("README.txt"), "not a table").unwrap();
+        dir
+    }
+
+    #[test]
+    fn syzygy_probing() {
+        let dir = synthetic_tablebase("probing");
+        let tablebase = SyzygyTablebase::open(&dir).unwrap();
+        assert_eq!(tablebase.tables(), (1, 1));
+        assert_eq!(tablebase.max_pieces(), 3);
+        let board = |fen| Board::from_fen(fen).unwrap();
+
+        let white_to_move = board("8/8/8/4k3/8/8/8/KQ6 w - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&white_to_move), Some(Wdl::Win));
+        assert_eq!(tablebase.probe_dtz(&white_to_move), Some(21));
+        // Only White to move is in the DTZ table, Black's only move Ka7 gets there
+        let black_to_move = board("k7/8/8/8/8/8/8/KQ6 b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&black_to_move), Some(Wdl::Loss));
+        assert_eq!(tablebase.probe_dtz(&black_to_move), Some(-22));
+        // Same table with the colors swapped
+        assert_eq!(tablebase.probe_wdl(&board("kq6/8/8/8/8/8/8/7K b - - 0 1")), Some(Wdl::Win));
+        // Taking the queen draws whatever the table says
+        let hanging = board("7K/8/8/8/8/8/1k6/1Q6 b - - 0 1");
+        assert_eq!(tablebase.probe_wdl(&hanging), Some(Wdl::Draw));
+        assert_eq!(tablebase.probe_dtz(&hanging), Some(0));
+
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/4k3/8/8/8/K7 w - - 0 1")), Some(Wdl::Draw));
+        assert_eq!(tablebase.probe_wdl(&board("8/8/8/4k3/8/8/8/KR6 w - - 0 1")), None);
+        assert_eq!(tablebase.probe_wdl(&board("4k3/8/8/8/8/8/8/4K2R w K - 0 1")), None);
+    }
+
+    #[test]
+    fn syzygy_invalid_files() {
+        let dir = synthetic_tablebase("invalid");
+        std::fs::write(dir.join("KRvK.rtbw"), [0xD7, 0x66, 0x0C, 0xA5]).unwrap();
+        assert!(matches!(SyzygyTablebase::open(&dir), Err(SyzygyError::Format(_))));
+        assert!(matches!(SyzygyTablebase::open(dir.join("missing")), Err(SyzygyError::Io(_))));
+
+        // A truncated table counts as missing once it's needed
+        std::fs::write(dir.join("KRvK.rtbw"), [0x71, 0xE8, 0x23, 0x5D, 0x01]).unwrap();
+        let tablebase = SyzygyTablebase::open(&dir).unwrap();
+        assert_eq!(tablebase.probe_wdl(&Board::from_fen("8/8/8/4k3/8/8/8/KR6 w - - 0 1").unwrap()), None);
+    }
+
+    #[test]
+    fn engine_uses_tablebases() {
+        let dir = synthetic_tablebase("engine");
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "1").unwrap();
+        assert!(matches!(engine.set_option("SyzygyPath", dir.join("missing").to_str().unwrap()), Err(EngineError::Tablebase(_))));
+        assert!(matches!(engine.set_option("SyzygyProbeLimit", "8"), Err(EngineError::InvalidOptionValue { .. })));
+        engine.set_option("SyzygyPath", dir.to_str().unwrap()).unwrap();
+
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/KQ6 w - - 0 1").unwrap();
+        engine.set_position(board, &[]).unwrap();
+        let result = engine.search(SearchLimits { depth: Some(2), ..Default::default() }).unwrap().unwrap();
+        assert_eq!(result.score, TB_WIN);
+        // Moves that let the king take the queen aren't wins and are never played
+        let mut after = board;
+        after.do_move_from_coord(result.best_move).unwrap();
+        let tablebase = SyzygyTablebase::open(&dir).unwrap();
+        assert_eq!(tablebase.probe_wdl(&after), Some(Wdl::Loss));
+
+        engine.set_option("SyzygyProbeLimit", "0").unwrap();
+        let result = engine.search(SearchLimits { depth: Some(2), ..Default::default() }).unwrap().unwrap();
+        assert!(result.score < TB_BOUND);
+    }
+
+    /// The value of every sampled position must follow from the values after each of its moves
+    fn assert_consistent(tablebase: &RetrogradeTablebase, fens: &[&str]) {
+        let rank = |dtm: Dtm| match dtm {
+            Dtm::Win(plies) => 1000 - plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => -1000 + plies as i32,
+        };
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let best = board
+                .list_all_valid_moves()
+                .into_iter()
+                .map(|mv| {
+                    let mut child = board;
+                    child.do_move_from_coord(mv).unwrap();
+                    match tablebase.probe_dtm(&child).unwrap() {
+                        Dtm::Win(plies) => Dtm::Loss(plies + 1),
+                        Dtm::Draw => Dtm::Draw,
+                        Dtm::Loss(plies) => Dtm::Win(plies + 1),
+                    }
+                })
+                .max_by_key(|&dtm| rank(dtm));
+            let expected = best.unwrap_or(if board.get_check() { Dtm::Loss(0) } else { Dtm::Draw });
+            assert_eq!(tablebase.probe_dtm(&board), Some(expected), "{fen}");
+        }
+    }
+
+    #[test]
+    fn retrograde_krk() {
+        let mut tablebase = RetrogradeTablebase::new();
+        tablebase.generate("KRvK").unwrap();
+        assert_eq!(tablebase.tables(), ["KRvK"]);
+        let probe = |fen| tablebase.probe_dtm(&Board::from_fen(fen).unwrap());
+        assert_eq!(probe("k6R/8/1K6/8/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(0)));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), Some(Dtm::Win(1)));
+        // Black to move takes the rook, colors swapped works the same way
+        assert_eq!(probe("8/8/8/8/8/8/1k6/1R2K3 b - - 0 1"), Some(Dtm::Draw));
+        assert_eq!(probe("8/8/8/8/8/1k6/8/K6r w - - 0 1"), Some(Dtm::Loss(0)));
+        // Mate in 16 is the longest
+        assert_eq!(probe("8/8/8/8/8/8/8/R3K2k w - - 0 1").map(|dtm| matches!(dtm, Dtm::Win(plies) if plies <= 31)), Some(true));
+        assert_eq!(probe("8/8/8/8/8/8/8/R3K2k w Q - 0 1"), None);
+        assert_eq!(probe("8/8/8/8/8/8/8/Q3K2k w - - 0 1"), None);
+        assert_consistent(&tablebase, &[
+            "8/8/8/4k3/8/8/8/R3K3 w - - 0 1",
+            "8/8/8/4k3/8/8/8/R3K3 b - - 0 1",
+            "7k/8/6K1/8/8/8/8/1R6 b - - 0 1",
+            "8/8/3K4/8/3k4/8/8/7r w - - 0 1",
+        ]);
+
+        // The engine keeps only the quickest mates at the root
+        let tablebase = std::sync::Arc::new(tablebase);
+        let mut engine = Engine::new();
+        engine.set_option("Threads", "1").unwrap();
+        assert!(matches!(engine.set_option("EndgameTables", "KXvK"), Err(EngineError::InvalidOptionValue { .. })));
+        engine.set_tablebase(Some(tablebase.clone())).unwrap();
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        engine.set_position(board, &[]).unwrap();
+        let result = engine.search(SearchLimits { depth: Some(2), ..Default::default() }).unwrap().unwrap();
+        assert_eq!(result.score, TB_WIN);
+        let mut after = board;
+        after.do_move_from_coord(result.best_move).unwrap();
+        let Some(Dtm::Win(plies)) = tablebase.probe_dtm(&board) else { panic!() };
+        assert_eq!(tablebase.probe_dtm(&after), Some(Dtm::Loss(plies - 1)));
+    }
+
+    #[test]
+    fn retrograde_kpk() {
+        let mut tablebase = RetrogradeTablebase::new();
+        tablebase.generate("KPvK").unwrap();
+        assert_eq!(tablebase.tables(), ["KPvK", "KQvK"]);
+        let probe = |fen| tablebase.probe_dtm(&Board::from_fen(fen).unwrap());
+        let wins = |fen| matches!(probe(fen), Some(Dtm::Win(_)));
+        // King on the sixth in front of its pawn wins whoever moves, with the opposition on the fifth only White to move draws
+        assert!(matches!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(_))));
+        assert!(wins("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"));
+        assert_eq!(probe("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"), Some(Dtm::Draw));
+        assert!(matches!(probe("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1"), Some(Dtm::Loss(_))));
+        assert!(wins("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1"));
+        // Rook pawns draw when the defending king reaches the corner
+        assert_eq!(probe("7k/8/8/8/7P/8/8/6K1 w - - 0 1"), Some(Dtm::Draw));
+        assert_consistent(&tablebase, &[
+            "8/8/8/8/8/4k3/4P3/4K3 w - - 0 1",
+            "8/1P6/8/8/8/2k5/8/K7 w - - 0 1",
+            "8/8/8/8/8/8/4p3/2K1k3 w - - 0 1",
+            "8/8/1k6/8/8/8/1P6/1K6 b - - 0 1",
+        ]);
+        assert!(matches!(tablebase.generate("KPvKP"), Err(RetrogradeError::Material(_))));
+        assert!(matches!(tablebase.generate("KQRBvK"), Err(RetrogradeError::Material(_))));
+        assert!(matches!(tablebase.generate("KQvQ"), Err(RetrogradeError::Material(_))));
+    }
+
+    /// Real Syzygy tables for KQvK, KRvK, KPvK and KRvKR, checked in under tests/syzygy
+    fn real_tablebase() -> SyzygyTablebase {
+        SyzygyTablebase::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy")).unwrap()
+    }
+
+    /// FEN of pieces given as FEN letters on squares numbered from a8 to h1
+    fn placement_fen(pieces: &[(char, usize)], turn: char) -> String {
+        let mut rows = [[None; 8]; 8];
+        for &(piece, square) in pieces {
+            rows[square / 8][square % 8] = Some(piece);
+        }
+        let rows: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let mut fen = String::new();
+                let mut empty = 0;
+                for square in row {
+                    match square {
+                        Some(piece) => {
+                            if empty > 0 {
+                                fen.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            fen.push(*piece);
+                        }
+                        None => empty += 1,
+                    }
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                }
+                fen
+            })
+            .collect();
+        format!("{} {turn} - - 0 1", rows.join("/"))
+    }
+
+    /// Compares every `step`th placement of a three-piece table with White stronger, and the same
+    /// position with the colors swapped, against the distances to mate of the retrograde tables
+    fn assert_matches_retrograde(tablebase: &impl Tablebase, retrograde: &RetrogradeTablebase, pieces: [char; 3], step: usize) {
+        for squares in (0..64 * 64 * 64).step_by(step).map(|i| [i / 4096, i / 64 % 64, i % 64]) {
+            let placed: Vec<(char, usize)> = pieces.into_iter().zip(squares).collect();
+            let shared = squares[0] == squares[1] || squares[1] == squares[2] || squares[0] == squares[2];
+            if shared || placed.iter().any(|&(piece, square)| piece == 'P' && !(8..56).contains(&square)) {
+                continue;
+            }
+            for turn in ['w', 'b'] {
+                let fen = placement_fen(&placed, turn);
+                let board = Board::from_fen(&fen).unwrap();
+                // The side to move can't be giving check
+                let Some(dtm) = retrograde.probe_dtm(&board) else {
+                    continue;
+                };
+                let wdl = tablebase.probe_wdl(&board);
+                let dtz = tablebase.probe_dtz(&board);
+                let swapped: Vec<(char, usize)> = placed
+                    .iter()
+                    .map(|&(piece, square)| {
+                        let piece = if piece.is_ascii_uppercase() { piece.to_ascii_lowercase() } else { piece.to_ascii_uppercase() };
+                        (piece, square ^ 56)
+                    })
+                    .collect();
+                let swapped = Board::from_fen(&placement_fen(&swapped, if turn == 'w' { 'b' } else { 'w' })).unwrap();
+                assert_eq!((tablebase.probe_wdl(&swapped), tablebase.probe_dtz(&swapped)), (wdl, dtz), "{fen}");
+
+                // The retrograde tables only promote to a queen, a rook can avoid stalemating. The
+                // DTZ of those wins isn't known either, as the probe only tries queen promotions.
+                let promotion = placed.iter().find(|&&(piece, square)| piece == 'P' && square < 16 && turn == 'w');
+                if let Some(&(_, square)) = promotion.filter(|_| dtm == Dtm::Draw) {
+                    let free = placed.iter().all(|&(_, other)| other != square - 8);
+                    let rook: Vec<(char, usize)> = placed
+                        .iter()
+                        .map(|&(piece, from)| if from == square { ('R', square - 8) } else { (piece, from) })
+                        .collect();
+                    let rook = Board::from_fen(&placement_fen(&rook, 'b')).unwrap();
+                    if free && retrograde.probe_wdl(&rook) == Some(Wdl::Loss) {
+                        assert_eq!(wdl, Some(Wdl::Win), "{fen}");
+                        continue;
+                    }
+                }
+
+                let expected = retrograde.probe_dtz(&board).unwrap();
+                assert_eq!(wdl, retrograde.probe_wdl(&board), "{fen}");
+                let dtz = dtz.unwrap();
+                assert_eq!(dtz.signum(), expected.signum(), "{fen}");
+                // Distances may be rounded up a ply. Without pawns only mate ends a win, pawn moves
+                // zero the counter before it.
+                if pieces.contains(&'P') {
+                    assert!(dtz.abs() <= expected.abs() + 1, "{fen}: {dtz} {expected}");
+                } else {
+                    assert!(dtz == expected || dtz == expected + expected.signum(), "{fen}: {dtz} {expected}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn syzygy_real_tables() {
+        let tablebase = real_tablebase();
+        let probe = |fen| {
+            let board = Board::from_fen(fen).unwrap();
+            (tablebase.probe_wdl(&board), tablebase.probe_dtz(&board))
+        };
+        // Mates in one, already mated and stalemated, with either side stronger
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("6q1/8/8/8/8/1k6/8/K7 b - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("8/8/8/8/8/1k6/8/K6r w - - 0 1"), (Some(Wdl::Loss), Some(-1)));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("k7/8/8/8/8/8/5q2/7K w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/KQ6 b - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("kq6/8/8/8/8/8/8/7K w - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("7K/8/8/8/8/8/1k6/1Q6 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        // Pawns, promoting is a zeroing move
+        assert_eq!(probe("8/4P3/8/8/8/k7/8/K7 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("7k/8/8/8/7P/8/8/6K1 w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        // The same material on both sides: a skewer wins the rook on the third ply
+        assert_eq!(probe("8/8/8/3k3r/8/1K6/8/R7 w - - 0 1"), (Some(Wdl::Win), Some(3)));
+        assert_eq!(probe("r7/8/1k6/8/3K3R/8/8/8 b - - 0 1"), (Some(Wdl::Win), Some(3)));
+        assert_eq!(probe("8/8/4k3/7r/8/4K3/8/R7 w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+    }
+
+    #[test]
+    fn syzygy_matches_retrograde() {
+        let tablebase = real_tablebase();
+        let mut retrograde = RetrogradeTablebase::new();
+        retrograde.generate("KRvK").unwrap();
+        retrograde.generate("KPvK").unwrap();
+        // A sample keeps debug runs short, a step of 1 checks every position
+        assert_matches_retrograde(&tablebase, &retrograde, ['K', 'Q', 'k'], 53);
+        assert_matches_retrograde(&tablebase, &retrograde, ['K', 'R', 'k'], 53);
+        assert_matches_retrograde(&tablebase, &retrograde, ['K', 'P', 'k'], 53);
+    }
+}
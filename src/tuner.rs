@@ -0,0 +1,264 @@
+use crate::board::{
+    Board,
+    BoardError,
+};
+use crate::evaluation::evaluate_with;
+use crate::params::EvalParams;
+use crate::pieces::PieceColor;
+use std::sync::{mpsc, Arc};
+use std::{fmt, fs, io, path::Path, thread};
+
+#[derive(Debug)]
+pub enum TunerError {
+    Io(io::Error),
+    /// Line `line` (counting from 1) of the position file couldn't be read
+    Parse { line: usize, desc: String },
+}
+pub type Result<T> = std::result::Result<T, TunerError>;
+
+impl std::error::Error for TunerError {}
+
+impl fmt::Display for TunerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TunerError::Io(err) => write!(f, "Could not read positions: {err}"),
+            TunerError::Parse { line, desc } => write!(f, "Invalid position on line {line}: {desc}"),
+        }
+    }
+}
+
+impl From<io::Error> for TunerError {
+    fn from(err: io::Error) -> Self {
+        TunerError::Io(err)
+    }
+}
+
+/// A position and the result of the game it was taken from
+#[derive(Copy, Clone, Debug)]
+pub struct TuningPosition {
+    pub board: Board,
+    /// 1.0 for a White win, 0.5 for a draw and 0.0 for a Black win
+    pub result: f64,
+}
+
+impl TuningPosition {
+    /// Reads a FEN followed by the game result, as `1-0`, `0-1`, `1/2-1/2` or a number between 0 and 1.
+    /// The result may be wrapped in brackets or quotes and preceded by a `c9` opcode, as in EPD files.
+    /// The move counters may be left out of the FEN.
+    pub fn parse(line: &str) -> std::result::Result<Self, String> {
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        let result = tokens.pop().ok_or("Empty line")?;
+        let result = match result.trim_matches(|c| matches!(c, '[' | ']' | '"' | ';')) {
+            "1-0" => 1.0,
+            "0-1" => 0.0,
+            "1/2-1/2" => 0.5,
+            number => match number.parse::<f64>() {
+                Ok(result) if (0.0..=1.0).contains(&result) => result,
+                _ => return Err(format!("Invalid result {result}")),
+            },
+        };
+        if tokens.last() == Some(&"c9") {
+            tokens.pop();
+        }
+        let mut fen = tokens.join(" ");
+        if tokens.len() == 4 {
+            fen.push_str(" 0 1");
+        }
+        let board = Board::from_fen(&fen).map_err(|err: BoardError| err.to_string())?;
+        Ok(TuningPosition { board, result })
+    }
+}
+
+/// Reads one [`TuningPosition`] per line, skipping blank lines and positions in check since
+/// their static evaluation says little about the outcome
+pub fn load_positions(path: impl AsRef<Path>) -> Result<Vec<TuningPosition>> {
+    let mut positions = Vec::new();
+    for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let position = TuningPosition::parse(line).map_err(|desc| TunerError::Parse { line: index + 1, desc })?;
+        if !position.board.get_check() {
+            positions.push(position);
+        }
+    }
+    Ok(positions)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TunerOptions {
+    /// 0 uses every available core
+    pub threads: usize,
+    /// Passes over all the weights, tuning also stops once a pass improves nothing
+    pub max_iterations: usize,
+    /// How far each weight is moved per try
+    pub step: i32,
+}
+
+impl Default for TunerOptions {
+    fn default() -> Self {
+        TunerOptions {
+            threads: 0,
+            max_iterations: 100,
+            step: 1,
+        }
+    }
+}
+
+/// Texel tuning: fits the evaluation parameters so that `sigmoid(eval)` predicts the game results of
+/// the positions as well as possible, by nudging one weight at a time while the mean squared error drops.
+/// Positions should be quiet, as they are scored with the static evaluation rather than a search.
+pub struct Tuner {
+    positions: Vec<TuningPosition>,
+    options: TunerOptions,
+    /// Scales centipawns for the sigmoid, see [`Tuner::fit_scaling`]
+    pub scaling: f64,
+}
+
+impl Tuner {
+    pub fn new(positions: Vec<TuningPosition>, options: TunerOptions) -> Self {
+        let threads = match options.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        Tuner {
+            positions,
+            options: TunerOptions { threads, ..options },
+            scaling: 1.0,
+        }
+    }
+
+    pub fn positions(&self) -> &[TuningPosition] {
+        &self.positions
+    }
+
+    /// Expected score for White given an evaluation in centipawns from White's point of view
+    pub fn sigmoid(&self, eval: f64) -> f64 {
+        sigmoid(self.scaling, eval)
+    }
+
+    /// Mean squared difference between the game results and the predictions made with `params`
+    pub fn error(&self, params: &EvalParams) -> f64 {
+        self.with_workers(|workers| workers.error(params, self.scaling))
+    }
+
+    /// Runs `f` with a worker thread for each chunk of the positions, which lasts until `f` returns
+    fn with_workers<R>(&self, f: impl FnOnce(&Workers) -> R) -> R {
+        let chunk_size = self.positions.len().div_ceil(self.options.threads).max(1);
+        thread::scope(|scope| {
+            let channels = self
+                .positions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let (job_sender, jobs) = mpsc::channel::<Job>();
+                    let (result_sender, results) = mpsc::channel();
+                    scope.spawn(move || {
+                        for (params, scaling) in jobs {
+                            let total: f64 = chunk.iter().map(|position| squared_error(position, &params, scaling)).sum();
+                            if result_sender.send(total).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    (job_sender, results)
+                })
+                .collect();
+            // The workers finish once the job senders go away with `workers`
+            let workers = Workers { channels, positions: self.positions.len() };
+            f(&workers)
+        })
+    }
+
+    /// Picks the sigmoid scaling that best fits the positions with the current `params`, so that tuning
+    /// changes the weights' shape rather than just their overall size. Returns the error at that scaling.
+    pub fn fit_scaling(&mut self, params: &EvalParams) -> f64 {
+        let (scaling, error) = self.with_workers(|workers| {
+            // Golden section search, the error is unimodal in the scaling
+            let ratio = (5f64.sqrt() - 1.0) / 2.0;
+            let (mut low, mut high) = (0.05, 5.0);
+            for _ in 0..40 {
+                let a = high - ratio * (high - low);
+                let b = low + ratio * (high - low);
+                if workers.error(params, a) < workers.error(params, b) {
+                    high = b;
+                } else {
+                    low = a;
+                }
+            }
+            let scaling = (low + high) / 2.0;
+            (scaling, workers.error(params, scaling))
+        });
+        self.scaling = scaling;
+        error
+    }
+
+    /// Local search from `params`. `progress` is told the error after every pass over the weights.
+    pub fn tune(&self, params: EvalParams, mut progress: impl FnMut(usize, f64)) -> EvalParams {
+        self.with_workers(|workers| {
+            let mut params = params;
+            let mut weights = params.weights();
+            let mut best = workers.error(&params, self.scaling);
+            for iteration in 0..self.options.max_iterations {
+                let mut improved = false;
+                for index in 0..weights.len() {
+                    for delta in [self.options.step, -self.options.step] {
+                        weights[index] += delta;
+                        params.set_weights(&weights);
+                        let error = workers.error(&params, self.scaling);
+                        if error < best {
+                            best = error;
+                            improved = true;
+                            break;
+                        }
+                        weights[index] -= delta;
+                    }
+                }
+                params.set_weights(&weights);
+                progress(iteration + 1, best);
+                if !improved {
+                    break;
+                }
+            }
+            params
+        })
+    }
+}
+
+/// Parameters and sigmoid scaling for a worker to score its positions with
+type Job = (Arc<EvalParams>, f64);
+
+/// Threads of [`Tuner::with_workers`], each scoring its own chunk of the positions
+struct Workers {
+    /// Jobs going out and the summed squared errors coming back, one pair per worker
+    channels: Vec<(mpsc::Sender<Job>, mpsc::Receiver<f64>)>,
+    positions: usize,
+}
+
+impl Workers {
+    /// [`Tuner::error`] with the sigmoid scaled by `scaling`
+    fn error(&self, params: &EvalParams, scaling: f64) -> f64 {
+        if self.positions == 0 {
+            return 0.0;
+        }
+        let params = Arc::new(params.clone());
+        for (jobs, _) in &self.channels {
+            jobs.send((Arc::clone(&params), scaling)).unwrap();
+        }
+        // Summed in chunk order so the same parameters always give the same error
+        let total: f64 = self.channels.iter().map(|(_, results)| results.recv().unwrap()).sum();
+        total / self.positions as f64
+    }
+}
+
+fn sigmoid(scaling: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scaling * eval / 400.0))
+}
+
+fn squared_error(position: &TuningPosition, params: &EvalParams, scaling: f64) -> f64 {
+    let eval = evaluate_with(&position.board, params);
+    let eval = match position.board.turn {
+        PieceColor::White => eval,
+        PieceColor::Black => -eval,
+    };
+    (position.result - sigmoid(scaling, eval as f64)).powi(2)
+}